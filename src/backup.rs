@@ -0,0 +1,339 @@
+use crate::config::LocationProps;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// generations kept per project when `backup.keep` is not configured
+pub static DEFAULT_KEEP: usize = 5;
+static UNKNOWN_COMMIT: &str = "unknown";
+
+/// a backup of a deployed jar in `location.tmp`
+#[derive(Debug, PartialEq)]
+pub struct Backup {
+    // seconds since unix epoch
+    pub timestamp: u64,
+    pub commit: String,
+    // the `git.ref` the jar is built from
+    pub git_ref: Option<String>,
+    pub path: PathBuf,
+}
+
+/// the deployed jar of a project: `<location.bin>/<package_name>.jar`
+pub fn deployed_jar(location: &LocationProps, package_name: &str) -> PathBuf {
+    Path::new(&location.bin).join(format!("{}.jar", package_name))
+}
+
+fn deploy_record(location: &LocationProps, package_name: &str) -> PathBuf {
+    Path::new(&location.bin).join(format!("{}.commit", package_name))
+}
+
+fn index_file(location: &LocationProps, package_name: &str) -> PathBuf {
+    Path::new(&location.tmp).join(format!("{}.backups", package_name))
+}
+
+/// record the commit id of the deployed jar, the next backup is named by it.
+///
+/// the `git.ref` the commit is checked out from is recorded in the second line.
+pub fn record_deploy(
+    location: &LocationProps,
+    package_name: &str,
+    commit: &str,
+    git_ref: Option<&str>,
+) -> io::Result<()> {
+    let record = match git_ref {
+        Some(git_ref) => format!("{}\n{}\n", commit, git_ref),
+        None => String::from(commit),
+    };
+    fs::write(deploy_record(location, package_name), record)
+}
+
+/// the commit id of the deployed jar
+pub fn deployed_commit(location: &LocationProps, package_name: &str) -> Option<String> {
+    deployed_record(location, package_name).map(|(commit, _)| commit)
+}
+
+/// the `git.ref` the deployed jar is built from, `None` when it is built from `git.branch`
+pub fn deployed_ref(location: &LocationProps, package_name: &str) -> Option<String> {
+    deployed_record(location, package_name).and_then(|(_, git_ref)| git_ref)
+}
+
+fn deployed_record(
+    location: &LocationProps,
+    package_name: &str,
+) -> Option<(String, Option<String>)> {
+    let record = fs::read_to_string(deploy_record(location, package_name)).ok()?;
+    let mut lines = record.lines().map(str::trim);
+    let commit = lines.next().filter(|s| !s.is_empty())?.to_owned();
+    let git_ref = lines.next().filter(|s| !s.is_empty()).map(String::from);
+    Some((commit, git_ref))
+}
+
+/// move the deployed jar into `location.tmp` as `<package_name>-<timestamp>-<commit>.jar`
+/// and keep the newest `keep` generations.
+///
+/// returns `None` when nothing is deployed yet.
+pub fn backup(
+    location: &LocationProps,
+    keep: usize,
+    package_name: &str,
+) -> io::Result<Option<Backup>> {
+    let current = deployed_jar(location, package_name);
+    if !current.exists() {
+        return Ok(None);
+    }
+    fs::create_dir_all(&location.tmp)?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (commit, git_ref) = deployed_record(location, package_name)
+        .unwrap_or_else(|| (String::from(UNKNOWN_COMMIT), None));
+    let path = Path::new(&location.tmp).join(format!(
+        "{}-{}-{}.jar",
+        package_name,
+        timestamp,
+        short_commit(&commit)
+    ));
+    println!("backup {} to {}", current.display(), path.display());
+    move_file(&current, &path)?;
+
+    let mut backups = list(location, package_name)?;
+    backups.retain(|b| b.path != path);
+    backups.push(Backup {
+        timestamp,
+        commit,
+        git_ref,
+        path,
+    });
+    let keep = keep.max(1);
+    if backups.len() > keep {
+        for old in backups.drain(..backups.len() - keep) {
+            println!("remove old backup {}", old.path.display());
+            if let Err(e) = fs::remove_file(&old.path) {
+                println!("cannot remove old backup {}: {}", old.path.display(), e);
+            }
+        }
+    }
+    write_index(location, package_name, &backups)?;
+    Ok(backups.pop())
+}
+
+/// the backups of a project, oldest first.
+///
+/// entries whose jar has been removed from `location.tmp` are skipped.
+pub fn list(location: &LocationProps, package_name: &str) -> io::Result<Vec<Backup>> {
+    let content = match fs::read_to_string(index_file(location, package_name)) {
+        Ok(content) => content,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let timestamp = fields.next()?.parse().ok()?;
+            let commit = String::from(fields.next()?);
+            let path = Path::new(&location.tmp).join(fields.next()?);
+            let git_ref = fields.next().map(String::from);
+            Some(Backup {
+                timestamp,
+                commit,
+                git_ref,
+                path,
+            })
+        })
+        .filter(|b| b.path.exists())
+        .collect())
+}
+
+/// restore a backup as the deployed jar with its commit and `git.ref`,
+/// the backup itself is kept in `location.tmp`.
+pub fn restore(location: &LocationProps, package_name: &str, backup: &Backup) -> io::Result<()> {
+    let deployed = deployed_jar(location, package_name);
+    println!(
+        "restore {} to {}",
+        backup.path.display(),
+        deployed.display()
+    );
+    fs::copy(&backup.path, &deployed)?;
+    record_deploy(
+        location,
+        package_name,
+        &backup.commit,
+        backup.git_ref.as_deref(),
+    )
+}
+
+fn write_index(location: &LocationProps, package_name: &str, backups: &[Backup]) -> io::Result<()> {
+    let content: String = backups
+        .iter()
+        .filter_map(|b| {
+            let file_name = b.path.file_name()?.to_str()?;
+            Some(match &b.git_ref {
+                Some(git_ref) => {
+                    format!("{} {} {} {}\n", b.timestamp, b.commit, file_name, git_ref)
+                }
+                None => format!("{} {} {}\n", b.timestamp, b.commit, file_name),
+            })
+        })
+        .collect();
+    fs::write(index_file(location, package_name), content)
+}
+
+/// format the backup timestamp as `yyyy-MM-dd HH:mm:ss` in UTC
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+fn short_commit(commit: &str) -> &str {
+    commit.get(..7).unwrap_or(commit)
+}
+
+// rename fails across file systems, fallback to copy and remove
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::backup::*;
+    use crate::testing;
+
+    fn location(name: &str) -> LocationProps {
+        let root = testing::temp_dir(&format!("backup-{}", name));
+        fs::create_dir_all(root.join("bin")).unwrap();
+        let path = |dir: &str| root.join(dir).to_string_lossy().into_owned();
+        LocationProps {
+            projects: path("projects"),
+            bin: path("bin"),
+            log: path("logs"),
+            tmp: path("bin/.temps"),
+            java: String::from("java"),
+        }
+    }
+
+    fn deploy(location: &LocationProps, commit: &str) {
+        fs::write(deployed_jar(location, "demo"), commit).unwrap();
+        record_deploy(location, "demo", commit, None).unwrap();
+    }
+
+    #[test]
+    fn backup_without_deployed_jar_does_nothing() {
+        let location = location("nothing");
+        assert_eq!(None, backup(&location, DEFAULT_KEEP, "demo").unwrap());
+        assert!(list(&location, "demo").unwrap().is_empty());
+    }
+
+    #[test]
+    fn backup_moves_deployed_jar_with_commit_in_file_name() {
+        let location = location("move");
+        deploy(&location, "0123456789abcdef");
+        let backup = backup(&location, DEFAULT_KEEP, "demo").unwrap().unwrap();
+        assert!(!deployed_jar(&location, "demo").exists());
+        assert_eq!("0123456789abcdef", backup.commit);
+        let file_name = backup.path.file_name().unwrap().to_str().unwrap();
+        assert!(file_name.starts_with("demo-"));
+        assert!(file_name.ends_with("-0123456.jar"));
+        assert_eq!(vec![backup], list(&location, "demo").unwrap());
+    }
+
+    #[test]
+    fn backup_keeps_configured_generations() {
+        let location = location("keep");
+        for commit in &["1aaaaaaa", "2aaaaaaa", "3aaaaaaa"] {
+            deploy(&location, commit);
+            backup(&location, 2, "demo").unwrap();
+        }
+        let commits: Vec<String> = list(&location, "demo")
+            .unwrap()
+            .into_iter()
+            .map(|b| b.commit)
+            .collect();
+        assert_eq!(vec!["2aaaaaaa", "3aaaaaaa"], commits);
+        assert_eq!(3, fs::read_dir(&location.tmp).unwrap().count());
+    }
+
+    #[test]
+    fn deploy_record_with_git_ref() {
+        let location = location("record");
+        record_deploy(&location, "demo", "0123456789abcdef", Some("v1.0.0")).unwrap();
+        assert_eq!(
+            Some(String::from("0123456789abcdef")),
+            deployed_commit(&location, "demo")
+        );
+        assert_eq!(
+            Some(String::from("v1.0.0")),
+            deployed_ref(&location, "demo")
+        );
+        deploy(&location, "fedcba9876543210");
+        assert_eq!(None, deployed_ref(&location, "demo"));
+    }
+
+    #[test]
+    fn restore_records_git_ref_of_backup() {
+        let location = location("restore-ref");
+        record_deploy(&location, "demo", "1aaaaaaa", Some("v1.0.0")).unwrap();
+        fs::write(deployed_jar(&location, "demo"), "1aaaaaaa").unwrap();
+        backup(&location, DEFAULT_KEEP, "demo").unwrap();
+        deploy(&location, "2aaaaaaa");
+        let backups = list(&location, "demo").unwrap();
+        assert_eq!(Some(String::from("v1.0.0")), backups[0].git_ref);
+        restore(&location, "demo", &backups[0]).unwrap();
+        assert_eq!(
+            Some(String::from("1aaaaaaa")),
+            deployed_commit(&location, "demo")
+        );
+        assert_eq!(
+            Some(String::from("v1.0.0")),
+            deployed_ref(&location, "demo")
+        );
+    }
+
+    #[test]
+    fn format_timestamp_in_utc() {
+        assert_eq!("1970-01-01 00:00:00", format_timestamp(0));
+        assert_eq!("2019-10-18 08:30:05", format_timestamp(1_571_387_405));
+        assert_eq!("2000-02-29 23:59:59", format_timestamp(951_868_799));
+    }
+
+    #[test]
+    fn restore_copies_backup_and_records_its_commit() {
+        let location = location("restore");
+        deploy(&location, "1aaaaaaa");
+        let backup = backup(&location, DEFAULT_KEEP, "demo").unwrap().unwrap();
+        deploy(&location, "2aaaaaaa");
+        restore(&location, "demo", &backup).unwrap();
+        let deployed = fs::read_to_string(deployed_jar(&location, "demo")).unwrap();
+        assert_eq!("1aaaaaaa", deployed);
+        assert_eq!(
+            Some(String::from("1aaaaaaa")),
+            deployed_commit(&location, "demo")
+        );
+        assert!(backup.path.exists());
+    }
+}
@@ -1,30 +1,115 @@
+use crate::backup;
+use crate::deploy;
+use crate::error::{Error, Result};
+use crate::git;
 use clap::crate_version;
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::env;
+use std::ffi::OsString;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+pub mod init;
+pub mod validate;
 
 static ARG_CONFIG: &str = "config";
-static ARG_LOCATION_PROJECTS: &str = "location-projects";
-static ARG_LOCATION_BIN: &str = "location-bin";
-static ARG_LOCATION_LOG: &str = "location-log";
-static ARG_LOCATION_TMP: &str = "location-tmp";
-static ARG_LOCATION_JAVA: &str = "location-java";
-static ARG_GIT_REMOTE: &str = "git-remote";
-static ARG_GIT_BRANCH: &str = "git-branch";
-static ARG_GIT_PREFIX: &str = "git-prefix";
-static ARG_GIT_NAME: &str = "git-name";
-static ARG_GIT_EMAIL: &str = "git-password";
-static ARG_GIT_USERNAME: &str = "git-username";
-static ARG_GIT_PASSWORD: &str = "git-password";
-static ARG_MAVEN_BIN: &str = "maven-bin";
-static ARG_MAVEN_REPOSITORY: &str = "maven-repository";
-static ARG_PACKAGE_ENV: &str = "package-env";
-static ARG_PACKAGE_TARGET: &str = "package-target";
-static ARG_DEPENDENCIES_UPDATE: &str = "dependencies-update";
+static ENV_CONFIG: &str = "AUTO_DEPLOY_CONFIG";
+static ENV_PREFIX: &str = "AUTO_DEPLOY_";
+static KEY_PROJECTS: &str = "projects";
+static KEY_ENV: &str = "env";
+static KEY_CREDENTIALS: &str = "credentials";
+// [git.credentials."<host或url前缀>"] 中可以配置的项，及其中的敏感信息
+static CREDENTIAL_KEYS: &[&str] = &[
+    "username",
+    "password",
+    "token",
+    "ssh_key",
+    "ssh_passphrase",
+    "use_agent",
+];
+static CREDENTIAL_SECRETS: &[&str] = &["password", "token", "ssh_passphrase"];
+static ARG_ENV: &str = "env";
+static ENV_ENV: &str = "AUTO_DEPLOY_ENV";
+static ARG_VERSION: &str = "version";
 
 static CONSTANTS_PROJECTS: &str = "PROJECTS";
+static CONSTANTS_PROJECT: &str = "PROJECT";
+static SUBCOMMAND_ROLLBACK: &str = "rollback";
+static ARG_ROLLBACK_GENERATION: &str = "generation";
+static ARG_ROLLBACK_LIST: &str = "list";
+static SUBCOMMAND_CONFIG: &str = "config";
+static SUBCOMMAND_CONFIG_SHOW: &str = "show";
+static ARG_CONFIG_SHOW_ORIGIN: &str = "origin";
+static SUBCOMMAND_CONFIG_VALIDATE: &str = "validate";
+static SUBCOMMAND_CONFIG_INIT: &str = "init";
+static ARG_CONFIG_INIT_FORCE: &str = "force";
+
+static ABSOLUTE_PATH: &str = "绝对路径";
+static RELATIVE_PATH: &str = "相对路径";
+
+// 配置项的值类型，环境变量及命令行参数的值按此类型转换
+#[derive(Clone, Copy)]
+enum Kind {
+    Str,
+    // 敏感信息，可以是 { env = "环境变量名" } 或 { file = "文件路径" }，显示时隐藏
+    Secret,
+    Bool,
+    Number,
+    // 逗号隔开的多个值
+    List,
+}
+
+// 可通过配置文件/环境变量/命令行参数设置的配置项，
+// 环境变量名为 AUTO_DEPLOY_ 加上大写的配置项路径，如 location.bin -> AUTO_DEPLOY_LOCATION_BIN
+struct Key {
+    // 配置文件中的路径
+    path: &'static str,
+    // 命令行参数名
+    arg: &'static str,
+    kind: Kind,
+    value_name: &'static str,
+    help: &'static str,
+}
+
+static KEYS: &[Key] = &[
+    Key { path: "location.projects", arg: "location-projects", kind: Kind::Str, value_name: ABSOLUTE_PATH, help: "设置项目所在目录" },
+    Key { path: "location.bin", arg: "location-bin", kind: Kind::Str, value_name: ABSOLUTE_PATH, help: "项目可执行文件所在目录" },
+    Key { path: "location.log", arg: "location-log", kind: Kind::Str, value_name: ABSOLUTE_PATH, help: "日志文件所在目录" },
+    Key { path: "location.tmp", arg: "location-tmp", kind: Kind::Str, value_name: ABSOLUTE_PATH, help: "原可执行文件的备份文件所在路径" },
+    Key { path: "location.java", arg: "location-java", kind: Kind::Str, value_name: ABSOLUTE_PATH, help: "java bin执行路径" },
+    Key { path: "git.remote", arg: "git-remote", kind: Kind::Str, value_name: "仓库名", help: "git远程仓库名" },
+    Key { path: "git.branch", arg: "git-branch", kind: Kind::Str, value_name: "分支名", help: "设置git远程分支名" },
+    Key { path: "git.ref", arg: "git-ref", kind: Kind::Str, value_name: "分支/tag/commit", help: "发布指定的分支、tag或commit id,如用于重新发布某个已知可用的版本" },
+    Key { path: "git.depth", arg: "git-depth", kind: Kind::Number, value_name: "深度", help: "浅克隆/拉取的提交深度,需要系统安装git命令(2.9及以上),默认拉取完整历史" },
+    Key { path: "git.single_branch", arg: "git-single-branch", kind: Kind::Bool, value_name: "true/false", help: "只克隆/拉取需要的分支或tag,默认false" },
+    Key { path: "git.prefix", arg: "git-prefix", kind: Kind::Str, value_name: "url前缀", help: "设置git的url前缀，例如 git@github.com/github.com/xxx" },
+    Key { path: "git.url", arg: "git-url", kind: Kind::Str, value_name: "url", help: "项目完整的远程仓库url,默认为 <git.prefix>/<项目名>.git" },
+    Key { path: "git.name", arg: "git-name", kind: Kind::Str, value_name: "name", help: "Sets name for git" },
+    Key { path: "git.email", arg: "git-email", kind: Kind::Str, value_name: "email", help: "Sets email for git" },
+    Key { path: "git.username", arg: "git-username", kind: Kind::Str, value_name: "username", help: "Sets username for git" },
+    Key { path: "git.password", arg: "git-password", kind: Kind::Secret, value_name: "password", help: "Sets password for git,会出现在ps输出中,建议在配置文件中使用 password = { env = \"GIT_TOKEN\" }" },
+    Key { path: "git.token", arg: "git-token", kind: Kind::Secret, value_name: "token", help: "https仓库的访问令牌,优先于password,建议在配置文件中使用 token = { env = \"GIT_TOKEN\" }" },
+    Key { path: "git.ssh_key", arg: "git-ssh-key", kind: Kind::Str, value_name: ABSOLUTE_PATH, help: "ssh私钥路径,默认依次尝试ssh-agent及 ~/.ssh/id_ed25519、id_ecdsa、id_rsa" },
+    Key { path: "git.ssh_passphrase", arg: "git-ssh-passphrase", kind: Kind::Secret, value_name: "passphrase", help: "ssh私钥的密码,建议在配置文件中使用 ssh_passphrase = { env = \"SSH_PASSPHRASE\" }" },
+    Key { path: "git.use_agent", arg: "git-use-agent", kind: Kind::Bool, value_name: "true/false", help: "是否优先使用ssh-agent,默认true" },
+    Key { path: "git.known_hosts", arg: "git-known-hosts", kind: Kind::Str, value_name: ABSOLUTE_PATH, help: "校验ssh主机密钥的known_hosts文件,默认为 ~/.ssh/known_hosts" },
+    Key { path: "git.host_key_check", arg: "git-host-key-check", kind: Kind::Str, value_name: "strict/trust-on-first-use/off", help: "ssh主机密钥校验方式,trust-on-first-use 会将未知主机的密钥记录到known_hosts,默认strict" },
+    Key { path: "maven.bin", arg: "maven-bin", kind: Kind::Str, value_name: ABSOLUTE_PATH, help: "maven可执行文件路径" },
+    Key { path: "maven.repository", arg: "maven-repository", kind: Kind::Str, value_name: ABSOLUTE_PATH, help: "maven仓库目录" },
+    Key { path: "maven.skip_tests", arg: "maven-skip-tests", kind: Kind::Bool, value_name: "true/false", help: "构建时是否跳过测试,默认跳过" },
+    Key { path: "package.env", arg: "package-env", kind: Kind::Str, value_name: "环境名", help: "当前执行环境名,对应spring.profiles.active" },
+    Key { path: "package.target", arg: "package-target", kind: Kind::Str, value_name: RELATIVE_PATH, help: "项目/模块内构建结果目录" },
+    Key { path: "dependencies.update", arg: "dependencies-update", kind: Kind::List, value_name: "依赖集合", help: "项目所需要强制更新的依赖,采用gradle形式版本,多个依赖使用逗号隔开,形如:\n site.zido:demo:-1.0.1,site.zido:demo2:0.0.2" },
+    Key { path: "backup.keep", arg: "backup-keep", kind: Kind::Number, value_name: "数量", help: "每个项目在备份目录中保留的备份数量,默认5个" },
+    Key { path: "startup.success", arg: "startup-success", kind: Kind::Str, value_name: "日志内容", help: "日志中出现此内容视为启动成功,*匹配同一行内的任意内容,默认为: Started * in * seconds" },
+    Key { path: "startup.failure", arg: "startup-failure", kind: Kind::List, value_name: "日志内容集合", help: "日志中出现其中任一内容视为启动失败并回滚,多个内容使用逗号隔开" },
+    Key { path: "startup.jvm_args", arg: "startup-jvm-args", kind: Kind::List, value_name: "JVM参数集合", help: "启动java时 -jar 前的JVM参数,多个参数使用逗号隔开,形如: -Xms256m,-Xmx1g" },
+    Key { path: "startup.timeout", arg: "startup-timeout", kind: Kind::Number, value_name: "秒", help: "启动超时时间,超时视为启动失败并回滚,默认120秒" },
+];
 
 #[derive(Debug, Deserialize)]
 pub struct DeployConfig {
@@ -33,271 +118,1111 @@ pub struct DeployConfig {
     pub maven: MavenProps,
     pub package: PackageProps,
     pub dependencies: DependenciesProps,
+    #[serde(default)]
+    pub backup: BackupProps,
+    #[serde(default)]
+    pub startup: StartupProps,
     pub projects: Option<Vec<String>>,
+    #[serde(skip)]
+    pub command: Command,
+    // 配置了 [projects.<name>] 的项目的最终配置
+    #[serde(skip)]
+    pub overrides: BTreeMap<String, DeployConfig>,
+}
+
+impl DeployConfig {
+    /// the effective config of a project: the global config overridden by its `[projects.<name>]` table
+    pub fn project(&self, project: &str) -> &DeployConfig {
+        self.overrides.get(project_key(project)).unwrap_or(self)
+    }
+}
+
+// 执行的命令，默认为发布
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum Command {
+    #[default]
+    Deploy,
+    // 回滚项目到第 generation 个备份(1为最新备份)，list 为 true 时只列出备份
+    Rollback {
+        generation: usize,
+        list: bool,
+    },
+    // 显示最终生效的配置，origin 为 true 时同时显示每项配置的来源
+    ConfigShow {
+        origin: bool,
+    },
+    // 校验配置文件
+    ConfigValidate,
+    // 生成配置文件，force 为 true 时覆盖已存在的配置文件
+    ConfigInit {
+        force: bool,
+    },
+    // --help 或 --version 的输出
+    Help(String),
 }
 
 #[derive(Debug, Deserialize)]
 pub struct LocationProps {
+    // 默认为 $HOME/auto-deploy/projects
     pub projects: String,
+    // 默认为 $HOME/auto-deploy/bin
     pub bin: String,
+    // 默认为 $HOME/auto-deploy/logs
     pub log: String,
+    // 默认为 $HOME/auto-deploy/bin/.temps
     pub tmp: String,
+    // 默认为 PATH 中的 java
     pub java: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct GitProps {
+    // 默认为 origin
     pub remote: String,
+    // 默认为 master
     pub branch: String,
+    // 必须配置
     pub prefix: String,
+    // 发布指定的分支、tag或commit id，而不是 branch 的最新提交
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+    // 浅克隆的提交深度，需要安装git命令，默认拉取完整历史
+    pub depth: Option<u32>,
+    // 默认为 false，只克隆/拉取需要的分支或tag
+    pub single_branch: Option<bool>,
     pub name: Option<String>,
     pub email: Option<String>,
     pub username: Option<String>,
-    pub password: Option<String>,
+    pub password: Option<Secret>,
+    // https仓库的访问令牌，优先于 password，未配置 username 时可使用任意用户名
+    pub token: Option<Secret>,
+    // ssh私钥路径，未配置时依次尝试 ~/.ssh/id_ed25519、id_ecdsa、id_rsa
+    pub ssh_key: Option<String>,
+    pub ssh_passphrase: Option<Secret>,
+    // 默认为 true，优先使用ssh-agent中的密钥
+    pub use_agent: Option<bool>,
+    // 默认为 ~/.ssh/known_hosts
+    pub known_hosts: Option<String>,
+    // 默认为 strict
+    pub host_key_check: Option<HostKeyCheck>,
+    // 项目完整的远程仓库url，一般在 [projects.<name>] 中配置，默认为 <prefix>/<项目名>.git
+    pub url: Option<String>,
+    // 各git服务器的认证信息，键为host或url前缀，匹配时替代以上的认证配置
+    #[serde(default)]
+    pub credentials: BTreeMap<String, CredentialProps>,
+}
+
+/// the credentials of the remotes matching a host or url prefix in `[git.credentials]`
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct CredentialProps {
+    pub username: Option<String>,
+    pub password: Option<Secret>,
+    pub token: Option<Secret>,
+    pub ssh_key: Option<String>,
+    pub ssh_passphrase: Option<Secret>,
+    pub use_agent: Option<bool>,
+}
+
+/// how the ssh host key of the git server is checked against `git.known_hosts`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyCheck {
+    /// unknown hosts and changed host keys are rejected
+    Strict,
+    /// the key of an unknown host is added to `known_hosts`, changed host keys are rejected
+    TrustOnFirstUse,
+    /// the host key is not checked
+    Off,
+}
+
+/// a secret value, shown as `***` in debug output
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: &str) -> Self {
+        Secret(String::from(secret))
+    }
+
+    /// the secret value
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"***\"")
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct MavenProps {
+    // 默认为 PATH 中的 mvn
     pub bin: String,
+    // 默认为 $HOME/.m2/repository
     pub repository: String,
+    // 构建时是否跳过测试，默认跳过
+    pub skip_tests: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PackageProps {
+    // 默认为 test
     pub env: String,
+    // 默认为 target
     pub target: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct DependenciesProps {
+    // 默认为空
     pub update: Vec<String>,
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct BackupProps {
+    // 每个项目保留的备份数量，默认5个
+    pub keep: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StartupProps {
+    // 日志中出现此内容视为启动成功，`*` 匹配同一行内的任意内容，默认为spring boot的 "Started * in * seconds"
+    pub success: Option<String>,
+    // 日志中出现其中任一内容视为启动失败
+    pub failure: Option<Vec<String>>,
+    // 启动java时 -jar 前的JVM参数
+    pub jvm_args: Option<Vec<String>>,
+    // 启动超时时间(秒)，默认120秒
+    pub timeout: Option<u64>,
+}
+
+/// the layer a configuration value came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Default,
+    // 配置文件路径
+    File(String),
+    // [env.<name>] 环境配置名
+    Profile(String),
+    // 环境变量名
+    Env(String),
+    // 命令行参数名
+    Cli(String),
+    // 从当前目录所在的git仓库推断
+    Repository(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(path) => write!(f, "file {}", path),
+            Origin::Profile(name) => write!(f, "env profile {}", name),
+            Origin::Env(name) => write!(f, "env {}", name),
+            Origin::Cli(arg) => write!(f, "cli {}", arg),
+            Origin::Repository(path) => write!(f, "git repository {}", path),
+        }
+    }
+}
+
+/// loads the configuration from layers, from low to high precedence:
+/// defaults -> TOML file -> `AUTO_DEPLOY_*` environment variables -> command line arguments.
+///
+/// the TOML file is `-c` / `AUTO_DEPLOY_CONFIG` / `default_config_path`, from high to low precedence.
+///
+/// # Example:
+/// ```no_run
+/// use auto_deploy::config::ConfigLoader;
+///
+/// let config = ConfigLoader::new("/etc/auto-deploy/config.toml")
+///     .args(vec!["auto-deploy", "--package-env", "online", "demo"])
+///     .load()
+///     .unwrap();
+/// assert_eq!("online", config.package.env);
+/// ```
+pub struct ConfigLoader {
+    default_config_path: String,
+    args: Vec<OsString>,
+    envs: Vec<(String, String)>,
+    current_dir: Option<PathBuf>,
+}
+
+impl ConfigLoader {
+    /// reads the arguments and environment variables of the current process
+    pub fn new(default_config_path: &str) -> Self {
+        ConfigLoader {
+            default_config_path: String::from(default_config_path),
+            args: env::args_os().collect(),
+            // non unicode variables can't be auto-deploy variables
+            envs: env::vars_os()
+                .filter_map(|(key, value)| {
+                    Some((key.into_string().ok()?, value.into_string().ok()?))
+                })
+                .collect(),
+            current_dir: env::current_dir().ok(),
+        }
+    }
+
+    /// replaces the current directory, where the project is inferred from when no project is given
+    pub fn current_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// replaces the command line arguments, the first one is the program name
+    pub fn args<I, T>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// replaces the environment variables
+    pub fn envs<I, K, V>(mut self, envs: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.envs = envs
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect();
+        self
+    }
+
+    pub fn load(&self) -> Result<DeployConfig> {
+        self.load_layers()?.into_config()
+    }
+
+    /// merges all layers without deserializing, so the values can be shown even if some are missing
+    pub fn load_layers(&self) -> Result<LayeredConfig> {
+        let mut layered = LayeredConfig {
+            path: String::new(),
+            value: Table::new(),
+            origins: BTreeMap::new(),
+            projects: BTreeMap::new(),
+            credentials: Table::new(),
+            profile: None,
+            warnings: Vec::new(),
+            command: Command::Deploy,
+        };
+        let matches = match app().get_matches_from_safe(&self.args) {
+            Ok(matches) => matches,
+            // --help 不是错误，由调用方输出后退出
+            Err(e) if !e.use_stderr() => {
+                layered.command = Command::Help(e.message);
+                return Ok(layered);
+            }
+            Err(e) => return Err(Error::Config(e.message)),
+        };
+        if matches.is_present(ARG_VERSION) {
+            layered.command = Command::Help(format!("auto-deploy {}", crate_version!()));
+            return Ok(layered);
+        }
+        let home = self
+            .envs
+            .iter()
+            .find(|(k, _)| k == "HOME")
+            .map(|(_, v)| PathBuf::from(v))
+            .or_else(dirs::home_dir);
+        for (path, value) in defaults(home.as_deref()) {
+            layered.set(path, value, Origin::Default);
+        }
+
+        let env = |name: &str| {
+            self.envs
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        };
+        layered.path = match matches.value_of(ARG_CONFIG) {
+            Some(config_path) => String::from(config_path),
+            None => env(ENV_CONFIG)
+                .map(String::from)
+                .unwrap_or_else(|| self.default_config_path.clone()),
+        };
+        let projects = match matches.subcommand() {
+            (name, Some(rollback)) if name == SUBCOMMAND_ROLLBACK => {
+                layered.command = Command::Rollback {
+                    generation: parse_number(rollback, ARG_ROLLBACK_GENERATION)?.unwrap_or(1),
+                    list: rollback.is_present(ARG_ROLLBACK_LIST),
+                };
+                rollback
+                    .values_of(CONSTANTS_PROJECT)
+                    .map(|projects| (projects, CONSTANTS_PROJECT))
+            }
+            (name, Some(config)) if name == SUBCOMMAND_CONFIG => {
+                if let Some(show) = config.subcommand_matches(SUBCOMMAND_CONFIG_SHOW) {
+                    layered.command = Command::ConfigShow {
+                        origin: show.is_present(ARG_CONFIG_SHOW_ORIGIN),
+                    };
+                } else if config.is_present(SUBCOMMAND_CONFIG_VALIDATE) {
+                    layered.command = Command::ConfigValidate;
+                } else if let Some(init) = config.subcommand_matches(SUBCOMMAND_CONFIG_INIT) {
+                    layered.command = Command::ConfigInit {
+                        force: init.is_present(ARG_CONFIG_INIT_FORCE),
+                    };
+                }
+                None
+            }
+            _ => matches
+                .values_of(CONSTANTS_PROJECTS)
+                .map(|projects| (projects, CONSTANTS_PROJECTS)),
+        };
+        // 生成配置文件时配置文件还不存在
+        if let Command::ConfigInit { .. } = layered.command {
+            return Ok(layered);
+        }
+        let origin = Origin::File(layered.path.clone());
+        let mut file = get_config_from_toml(&layered.path)?;
+        if world_readable(Path::new(&layered.path)) {
+            layered.warnings.push(format!(
+                "{} is readable by all users, it may contain secrets, run `chmod 600 {}`",
+                layered.path, layered.path
+            ));
+        }
+        // projects 为数组时是发布的项目名，为表时是各项目覆盖的配置
+        if let Some(Value::Table(_)) = file.get(KEY_PROJECTS) {
+            for (name, leaves) in take_tables(&mut file, KEY_PROJECTS, &layered.path)? {
+                layered
+                    .projects
+                    .insert(String::from(project_key(&name)), leaves);
+            }
+        }
+        let mut profiles = take_tables(&mut file, KEY_ENV, &layered.path)?;
+        if let Some(Value::Table(git)) = file.get_mut("git") {
+            if let Some(credentials) = git.remove(KEY_CREDENTIALS) {
+                layered.credentials =
+                    take_credentials(credentials, &env, &layered.path, &mut layered.warnings)?;
+            }
+        }
+        let mut leaves = Vec::new();
+        flatten("", file, &mut leaves);
+        for (path, value) in leaves {
+            layered.set(&path, value, origin.clone());
+        }
+
+        let profile = matches
+            .value_of(ARG_ENV)
+            .or_else(|| env(ENV_ENV))
+            .map(String::from);
+        if let Some(name) = profile {
+            let leaves = profiles.remove(&name).ok_or_else(|| {
+                let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                Error::Config(format!(
+                    "配置文件错误:{} 没有环境配置 [env.{}], 可选的环境: {}",
+                    layered.path,
+                    name,
+                    names.join(", ")
+                ))
+            })?;
+            let origin = Origin::Profile(name.clone());
+            // spring profile 默认与环境名相同
+            layered.set("package.env", Value::String(name.clone()), origin.clone());
+            for (path, value) in leaves {
+                layered.set(&path, value, origin.clone());
+            }
+            layered.profile = Some(name);
+        }
+
+        for key in KEYS {
+            let name = env_name(key.path);
+            if let Some(value) = env(&name) {
+                layered.set(key.path, parse_value(key, value)?, Origin::Env(name));
+            }
+        }
+        for key in KEYS {
+            if let Some(value) = matches.value_of(key.arg) {
+                if let Kind::Secret = key.kind {
+                    layered.warnings.push(format!(
+                        "--{} is visible to other users in ps output and shell history, \
+                         use {} = {{ env = \"...\" }} in the config file instead",
+                        key.arg, key.path
+                    ));
+                }
+                let origin = Origin::Cli(format!("--{}", key.arg));
+                layered.set(key.path, parse_value(key, value)?, origin);
+            }
+        }
+
+        for key in KEYS.iter().filter(|k| matches!(k.kind, Kind::Secret)) {
+            if let Some(value) = layered.get(key.path).filter(|v| v.is_table()).cloned() {
+                let prefix = format!("{}.", key.path);
+                let origin = layered
+                    .origins
+                    .iter()
+                    .find(|(path, _)| path.starts_with(&prefix))
+                    .map(|(_, origin)| origin.clone())
+                    .unwrap_or(Origin::Default);
+                let secret = resolve_secret(key.path, value, &env, &mut layered.warnings)?;
+                layered.set(key.path, secret, origin);
+            }
+            for leaves in layered.projects.values_mut() {
+                let prefix = format!("{}.", key.path);
+                let (reference, rest): (Vec<_>, Vec<_>) = leaves
+                    .drain(..)
+                    .partition(|(path, _)| path.starts_with(&prefix));
+                *leaves = rest;
+                if !reference.is_empty() {
+                    let table = reference
+                        .into_iter()
+                        .map(|(path, value)| (path[prefix.len()..].to_owned(), value))
+                        .collect();
+                    let secret =
+                        resolve_secret(key.path, Value::Table(table), &env, &mut layered.warnings)?;
+                    leaves.push((String::from(key.path), secret));
+                }
+            }
+        }
+
+        if let Some((projects, arg)) = projects {
+            let projects = projects.map(|p| Value::String(String::from(p))).collect();
+            layered.set(
+                "projects",
+                Value::Array(projects),
+                Origin::Cli(String::from(arg)),
+            );
+        } else if layered.command == Command::Deploy && layered.get(KEY_PROJECTS).is_none() {
+            layered.infer_project(self.current_dir.as_deref());
+        }
+        Ok(layered)
+    }
+}
+
+/// the merged configuration and the layer each value came from
+pub struct LayeredConfig {
+    // 配置文件路径
+    path: String,
+    value: Table,
+    origins: BTreeMap<String, Origin>,
+    // [projects.<name>] 中覆盖全局配置的值
+    projects: BTreeMap<String, Vec<(String, Value)>>,
+    // [git.credentials."<host>"] 各git服务器的认证信息，host中可能包含`.`，不参与分层合并
+    credentials: Table,
+    // 选择的 [env.<name>] 环境配置
+    profile: Option<String>,
+    warnings: Vec<String>,
+    command: Command,
+}
+
+impl LayeredConfig {
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// the layer of a value, eg. `origin("location.bin")`
+    pub fn origin(&self, path: &str) -> Option<&Origin> {
+        self.origins.get(path)
+    }
+
+    /// the config file path
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// problems that do not stop loading, eg. the config file is readable by all users
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// the effective value of a dotted path, eg. `get("location.bin")`
+    pub fn get(&self, path: &str) -> Option<&Value> {
+        let mut table = &self.value;
+        let mut keys: Vec<&str> = path.split('.').collect();
+        let last = keys.pop()?;
+        for key in keys {
+            table = table.get(key)?.as_table()?;
+        }
+        table.get(last)
+    }
+
+    /// the effective values as `key = value` lines, with their layer when `origin` is true
+    pub fn show(&self, origin: bool) -> String {
+        let mut leaves = Vec::new();
+        flatten("", self.value.clone(), &mut leaves);
+        let mut result = format!("# config file: {}\n", self.path);
+        if let Some(profile) = &self.profile {
+            result.push_str(&format!("# env: {}\n", profile));
+        }
+        for (path, value) in leaves {
+            result.push_str(&format!("{} = {}", path, redact(&path, &value)));
+            if let (true, Some(layer)) = (origin, self.origins.get(&path)) {
+                result.push_str(&format!("  # {}", layer));
+            }
+            result.push('\n');
+        }
+        for (name, leaves) in &self.projects {
+            for (path, value) in leaves {
+                result.push_str(&format!(
+                    "{}.\"{}\".{} = {}",
+                    KEY_PROJECTS,
+                    name,
+                    path,
+                    redact(path, value)
+                ));
+                if origin {
+                    result.push_str(&format!("  # file {}", self.path));
+                    if let (true, Some(layer)) = (self.overridden(path), self.origins.get(path)) {
+                        result.push_str(&format!(", overridden by {}", layer));
+                    }
+                }
+                result.push('\n');
+            }
+        }
+        for (host, credential) in &self.credentials {
+            for (key, value) in credential.as_table().into_iter().flatten() {
+                let value = if CREDENTIAL_SECRETS.contains(&key.as_str()) {
+                    Value::String(String::from("***"))
+                } else {
+                    value.clone()
+                };
+                result.push_str(&format!(
+                    "git.{}.\"{}\".{} = {}",
+                    KEY_CREDENTIALS, host, key, value
+                ));
+                if origin {
+                    result.push_str(&format!("  # file {}", self.path));
+                }
+                result.push('\n');
+            }
+        }
+        result
+    }
+
+    pub fn into_config(self) -> Result<DeployConfig> {
+        self.build()
+    }
+
+    fn build(&self) -> Result<DeployConfig> {
+        let mut config: DeployConfig = Value::Table(self.value())
+            .try_into()
+            .map_err(|e| Error::Config(format!("配置文件错误:{} {}", self.path, e)))?;
+        if self.command == Command::Deploy && config.projects.as_ref().is_none_or(|p| p.is_empty())
+        {
+            return Err(Error::Config(String::from(
+                "need provide projects or run in a git repository",
+            )));
+        }
+        for project in config.projects.iter().flatten() {
+            let name = project_key(project);
+            let leaves = match self.projects.get(name) {
+                Some(leaves) => leaves,
+                None => continue,
+            };
+            let mut value = self.value();
+            for (path, leaf) in leaves {
+                if !self.overridden(path) {
+                    insert(&mut value, path, leaf.clone());
+                }
+            }
+            let project_config = Value::Table(value).try_into().map_err(|e| {
+                Error::Config(format!(
+                    "配置文件错误:{} projects.{} {}",
+                    self.path, name, e
+                ))
+            })?;
+            config.overrides.insert(String::from(name), project_config);
+        }
+        config.command = self.command.clone();
+        Ok(config)
+    }
+
+    // 合并后的值及 git.credentials
+    fn value(&self) -> Table {
+        let mut value = self.value.clone();
+        if !self.credentials.is_empty() {
+            let path = format!("git.{}", KEY_CREDENTIALS);
+            insert(&mut value, &path, Value::Table(self.credentials.clone()));
+        }
+        value
+    }
+
+    // 没有指定项目时，从当前目录所在的git仓库推断项目名及分支
+    fn infer_project(&mut self, dir: Option<&Path>) {
+        let string = |value: Option<&Value>| value.and_then(Value::as_str).map(String::from);
+        let remote = string(self.get("git.remote")).unwrap_or_default();
+        let prefix = string(self.get("git.prefix")).unwrap_or_default();
+        let local = match dir.and_then(|dir| git::local_project(dir, &remote, &prefix)) {
+            Some(local) => local,
+            None => return,
+        };
+        let origin = Origin::Repository(dir.unwrap_or(Path::new(".")).display().to_string());
+        println!("infer project {} from {}", local.project, origin);
+        let projects = vec![Value::String(local.project)];
+        self.set(KEY_PROJECTS, Value::Array(projects), origin.clone());
+        if let Some(url) = local.url {
+            // 不在 git.prefix 下的远程仓库
+            if !self.chosen("git.url") {
+                self.set("git.url", Value::String(url), origin.clone());
+            }
+        }
+        if let Some(branch) = local.branch {
+            // 当前分支优先于配置文件，低于选择的环境配置、环境变量及命令行参数
+            if !self.chosen("git.branch") {
+                self.set("git.branch", Value::String(branch), origin);
+            }
+        }
+    }
+
+    // 环境变量及命令行参数优先于 [projects.<name>] 中的配置
+    fn overridden(&self, path: &str) -> bool {
+        matches!(
+            self.origins.get(path),
+            Some(Origin::Env(_)) | Some(Origin::Cli(_))
+        )
+    }
+
+    // 使用 --env 选择的环境配置同样优先于从git仓库推断的值
+    fn chosen(&self, path: &str) -> bool {
+        self.overridden(path) || matches!(self.origins.get(path), Some(Origin::Profile(_)))
+    }
+
+    fn set(&mut self, path: &str, value: Value, origin: Origin) {
+        insert(&mut self.value, path, value);
+        self.origins
+            .retain(|k, _| !k.starts_with(&format!("{}.", path)));
+        self.origins.insert(String::from(path), origin);
+    }
+}
+
 // 获取发布配置
 //
-// 配置规则：可通过全局配置和命令行参数进行配置，命令行参数优先。
-// 全局配置会从 命令行参数 -c / 全局环境变量 / 默认配置地址 default_config_path 读取，优先级从高到低
-pub fn get_config(default_config_path: &str) -> DeployConfig {
-    let matches = get_app_args();
-    let config_path = match matches.value_of(ARG_CONFIG) {
-        Some(config_path) => String::from(config_path),
-        None => env::var(ARG_CONFIG).unwrap_or(String::from(default_config_path)),
+// 配置规则：默认值 / 配置文件 / AUTO_DEPLOY_* 环境变量 / 命令行参数，优先级从低到高。
+// 配置文件会从 命令行参数 -c / 环境变量 AUTO_DEPLOY_CONFIG / 默认配置地址 default_config_path 读取，优先级从高到低
+pub fn get_config(default_config_path: &str) -> Result<DeployConfig> {
+    ConfigLoader::new(default_config_path).load()
+}
+
+// 敏感信息: 字符串 / { env = "环境变量名" } / { file = "文件路径" }
+fn resolve_secret<'a, F>(
+    path: &str,
+    value: Value,
+    env: &F,
+    warnings: &mut Vec<String>,
+) -> Result<Value>
+where
+    F: Fn(&str) -> Option<&'a str>,
+{
+    let table = match value {
+        Value::String(_) => return Ok(value),
+        Value::Table(table) => table,
+        _ => Table::new(),
     };
-    let global_config = get_config_from_toml(&config_path);
-
-    DeployConfig {
-        location: LocationProps {
-            projects: (&matches)
-                .value_of(ARG_LOCATION_PROJECTS)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.location.projects),
-            bin: (&matches)
-                .value_of(ARG_LOCATION_BIN)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.location.bin),
-            log: (&matches)
-                .value_of(ARG_LOCATION_LOG)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.location.log),
-            tmp: (&matches)
-                .value_of(ARG_LOCATION_TMP)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.location.tmp),
-            java: (&matches)
-                .value_of(ARG_LOCATION_JAVA)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.location.java),
-        },
-        git: GitProps {
-            remote: (&matches)
-                .value_of(ARG_GIT_REMOTE)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.git.remote),
-            branch: (&matches)
-                .value_of(ARG_GIT_BRANCH)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.git.branch),
-            prefix: (&matches)
-                .value_of(ARG_GIT_PREFIX)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.git.prefix),
-            name: (&matches)
-                .value_of(ARG_GIT_NAME)
-                .and_then(|s| Some(String::from(s)))
-                .or(global_config.git.username),
-            email: (&matches)
-                .value_of(ARG_GIT_EMAIL)
-                .and_then(|s| Some(String::from(s)))
-                .or(global_config.git.name),
-            username: (&matches)
-                .value_of(ARG_GIT_USERNAME)
-                .and_then(|s| Some(String::from(s)))
-                .or(global_config.git.email),
-            password: (&matches)
-                .value_of(ARG_GIT_PASSWORD)
-                .and_then(|s| Some(String::from(s)))
-                .or(global_config.git.password),
-        },
-        maven: MavenProps {
-            bin: (&matches)
-                .value_of(ARG_MAVEN_BIN)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.maven.bin),
-            repository: (&matches)
-                .value_of(ARG_MAVEN_REPOSITORY)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.maven.repository),
-        },
-        package: PackageProps {
-            env: (&matches)
-                .value_of(ARG_PACKAGE_ENV)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.package.env),
-            target: (&matches)
-                .value_of(ARG_PACKAGE_TARGET)
-                .and_then(|s| Some(String::from(s)))
-                .unwrap_or(global_config.package.target),
-        },
-        dependencies: DependenciesProps {
-            update: if let Some(dependencies) = (&matches).values_of(ARG_DEPENDENCIES_UPDATE) {
-                dependencies.map(|s| String::from(s)).collect()
-            } else {
-                global_config.dependencies.update
-            },
-        },
-        projects: if let Some(projects) = (&matches).values_of(CONSTANTS_PROJECTS) {
-            Some(projects.map(|s| String::from(s)).collect())
+    let secret = match (table.get("env"), table.get("file"), table.len()) {
+        (Some(Value::String(name)), None, 1) => env(name).map(String::from).ok_or_else(|| {
+            Error::Config(format!(
+                "{}: environment variable {} is not set",
+                path, name
+            ))
+        })?,
+        (None, Some(Value::String(file)), 1) => {
+            if world_readable(Path::new(file)) {
+                warnings.push(format!(
+                    "{} is readable by all users, run `chmod 600 {}`",
+                    file, file
+                ));
+            }
+            fs::read_to_string(file)
+                .map_err(|e| Error::Config(format!("{}: cannot read {}: {}", path, file, e)))?
+                .trim_end_matches(&['\r', '\n'][..])
+                .to_owned()
+        }
+        _ => {
+            return Err(Error::Config(format!(
+                "{} must be a string, {{ env = \"...\" }} or {{ file = \"...\" }}",
+                path
+            )))
+        }
+    };
+    Ok(Value::String(secret))
+}
+
+// 显示配置时隐藏敏感信息
+fn redact(path: &str, value: &Value) -> Value {
+    let secret = KEYS
+        .iter()
+        .any(|k| matches!(k.kind, Kind::Secret) && k.path == path);
+    if secret {
+        Value::String(String::from("***"))
+    } else {
+        value.clone()
+    }
+}
+
+#[cfg(unix)]
+fn world_readable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|m| m.permissions().mode() & 0o004 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn world_readable(_path: &Path) -> bool {
+    false
+}
+
+// 项目名前后的 / 及 \\ 会被忽略
+fn project_key(project: &str) -> &str {
+    project.trim_matches(&['/', '\\'][..])
+}
+
+// the `[git.credentials."<host>"]` tables, with the secrets resolved
+fn take_credentials<'a, F>(
+    credentials: Value,
+    env: &F,
+    path: &str,
+    warnings: &mut Vec<String>,
+) -> Result<Table>
+where
+    F: Fn(&str) -> Option<&'a str>,
+{
+    let not_table = |key: &str| {
+        Error::Config(format!(
+            "配置文件错误:{} git.{}{} must be a table",
+            path, KEY_CREDENTIALS, key
+        ))
+    };
+    let mut credentials = match credentials {
+        Value::Table(credentials) => credentials,
+        _ => return Err(not_table("")),
+    };
+    for (host, credential) in credentials.iter_mut() {
+        let credential = credential
+            .as_table_mut()
+            .ok_or_else(|| not_table(&format!(".\"{}\"", host)))?;
+        for key in CREDENTIAL_SECRETS {
+            if let Some(value) = credential.remove(*key) {
+                let secret_path = format!("git.{}.\"{}\".{}", KEY_CREDENTIALS, host, key);
+                let secret = resolve_secret(&secret_path, value, env, warnings)?;
+                credential.insert(String::from(*key), secret);
+            }
+        }
+    }
+    Ok(credentials)
+}
+
+// remove the `[<key>.<name>]` tables from the config file, returns the leaf values of each table
+fn take_tables(
+    file: &mut Table,
+    key: &str,
+    path: &str,
+) -> Result<BTreeMap<String, Vec<(String, Value)>>> {
+    let tables = match file.remove(key) {
+        Some(Value::Table(tables)) => tables,
+        Some(_) => {
+            return Err(Error::Config(format!(
+                "配置文件错误:{} {} must be a table",
+                path, key
+            )))
+        }
+        None => return Ok(BTreeMap::new()),
+    };
+    let mut result = BTreeMap::new();
+    for (name, value) in tables {
+        let table = match value {
+            Value::Table(table) => table,
+            _ => {
+                return Err(Error::Config(format!(
+                    "配置文件错误:{} {}.{} must be a table",
+                    path, key, name
+                )))
+            }
+        };
+        let mut leaves = Vec::new();
+        flatten("", table, &mut leaves);
+        result.insert(name, leaves);
+    }
+    Ok(result)
+}
+
+// set the value of a dotted path, the missing tables on the path are created
+fn insert(table: &mut Table, path: &str, value: Value) {
+    let mut table = table;
+    let mut keys: Vec<&str> = path.split('.').collect();
+    let last = keys.pop().unwrap_or(path);
+    for key in keys {
+        let entry = table
+            .entry(String::from(key))
+            .or_insert_with(|| Value::Table(Table::new()));
+        if !entry.is_table() {
+            *entry = Value::Table(Table::new());
+        }
+        table = match entry {
+            Value::Table(table) => table,
+            _ => unreachable!(),
+        };
+    }
+    table.insert(String::from(last), value);
+}
+
+// 除 git.prefix 外的配置项都有默认值，路径相关的默认值在 $HOME 下
+fn defaults(home: Option<&Path>) -> Vec<(&'static str, Value)> {
+    let string = |s: &str| Value::String(String::from(s));
+    let mut defaults = vec![
+        ("location.java", string("java")),
+        ("git.remote", string("origin")),
+        ("git.branch", string("master")),
+        ("git.single_branch", Value::Boolean(false)),
+        ("git.use_agent", Value::Boolean(true)),
+        ("git.host_key_check", string("strict")),
+        ("maven.bin", string("mvn")),
+        ("maven.skip_tests", Value::Boolean(true)),
+        ("package.env", string("test")),
+        ("package.target", string("target")),
+        ("dependencies.update", Value::Array(Vec::new())),
+        ("backup.keep", Value::Integer(backup::DEFAULT_KEEP as i64)),
+        (
+            "startup.success",
+            Value::String(String::from(deploy::DEFAULT_STARTUP_SUCCESS)),
+        ),
+        (
+            "startup.failure",
+            Value::Array(
+                deploy::DEFAULT_STARTUP_FAILURE
+                    .iter()
+                    .map(|s| Value::String(String::from(*s)))
+                    .collect(),
+            ),
+        ),
+        ("startup.jvm_args", Value::Array(Vec::new())),
+        (
+            "startup.timeout",
+            Value::Integer(deploy::DEFAULT_STARTUP_TIMEOUT_SECS as i64),
+        ),
+    ];
+    if let Some(home) = home {
+        let path = |path: &str| Value::String(home.join(path).to_string_lossy().into_owned());
+        defaults.push(("location.projects", path("auto-deploy/projects")));
+        defaults.push(("location.bin", path("auto-deploy/bin")));
+        defaults.push(("location.log", path("auto-deploy/logs")));
+        defaults.push(("location.tmp", path("auto-deploy/bin/.temps")));
+        defaults.push(("maven.repository", path(".m2/repository")));
+        defaults.push(("git.known_hosts", path(".ssh/known_hosts")));
+    }
+    defaults
+}
+
+fn env_name(path: &str) -> String {
+    format!("{}{}", ENV_PREFIX, path.replace('.', "_").to_uppercase())
+}
+
+fn parse_value(key: &Key, value: &str) -> Result<Value> {
+    let invalid = |expected: &str| {
+        Error::Config(format!(
+            "{} must be {}, found: {}",
+            key.path, expected, value
+        ))
+    };
+    Ok(match key.kind {
+        Kind::Str | Kind::Secret => Value::String(String::from(value)),
+        Kind::Bool => Value::Boolean(value.parse().map_err(|_| invalid("true or false"))?),
+        Kind::Number => Value::Integer(value.parse().map_err(|_| invalid("a number"))?),
+        Kind::List => Value::Array(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(|s| Value::String(String::from(s)))
+                .collect(),
+        ),
+    })
+}
+
+fn parse_number(matches: &ArgMatches, name: &str) -> Result<Option<usize>> {
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| Error::Config(format!("{} must be a number, found: {}", name, value))),
+        None => Ok(None),
+    }
+}
+
+// the leaf values of a table with their dotted paths
+fn flatten(prefix: &str, table: Table, leaves: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let path = if prefix.is_empty() {
+            key
         } else {
-            panic!("need provide projects")
-        },
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            Value::Table(table) => flatten(&path, table, leaves),
+            value => leaves.push((path, value)),
+        }
     }
 }
 
-fn get_config_from_toml<'a, P: AsRef<Path>>(path: P) -> DeployConfig {
+fn get_config_from_toml<P: AsRef<Path>>(path: P) -> Result<Table> {
     if let Ok(contents) = fs::read_to_string(path.as_ref()) {
-        toml::from_str::<DeployConfig>(&contents).unwrap()
+        contents
+            .parse::<Value>()
+            .map_err(|e| Error::Config(format!("配置文件错误:{} {}", path.as_ref().display(), e)))
+            .map(|value| match value {
+                Value::Table(table) => table,
+                _ => Table::new(),
+            })
     } else {
-        panic!("配置文件错误:无法访问{}", path.as_ref().display())
-    }
-}
-
-fn get_app_args<'a>() -> clap::ArgMatches<'a> {
-    let absolute_path = "绝对路径";
-    let relative_path = "相对路径";
-    with_matches(App::new("auto-deploy")
-    .version(crate_version!())
-    .author("zido. <wuhongxu1208@gmail.com>")
-    .about("自动发布项目到服务器，包含从git拉取->校验并构建项目->备份原版本->发布/回滚新版本")
-    .arg(Arg::with_name(ARG_CONFIG)
-        .short("c")
-        .long(ARG_CONFIG)
-        .value_name("配置文件路径")
-        .takes_value(true)
-        .help("设置配置文件路径"))
-    .arg(Arg::with_name(ARG_LOCATION_PROJECTS)
-        .long(ARG_LOCATION_PROJECTS)
-        .value_name(absolute_path)
-        .help("设置项目所在目录")
-        .takes_value(true))
-    .arg(Arg::with_name(ARG_LOCATION_BIN)
-        .long(ARG_LOCATION_BIN)
-        .value_name(absolute_path)
-        .takes_value(true)
-        .help("项目可执行文件所在目录"))
-    .arg(Arg::with_name(ARG_LOCATION_LOG)
-        .long(ARG_LOCATION_LOG)
-        .value_name(absolute_path)
-        .takes_value(true)
-        .help("日志文件所在目录"))
-    .arg(Arg::with_name(ARG_LOCATION_TMP)
-        .long(ARG_LOCATION_TMP)
-        .value_name(absolute_path)
-        .takes_value(true)
-        .help("原可执行文件的备份文件所在路径"))
-    .arg(Arg::with_name(ARG_LOCATION_JAVA)
-        .long(ARG_LOCATION_JAVA)
-        .value_name(absolute_path)
-        .takes_value(true)
-        .help("java bin执行路径"))
-    .arg(Arg::with_name(ARG_GIT_REMOTE)
-        .long(ARG_GIT_REMOTE)
-        .value_name("仓库名")
-        .takes_value(true)
-        .help("git远程仓库名"))
-    .arg(Arg::with_name(ARG_GIT_BRANCH)
-        .long(ARG_GIT_BRANCH)
-        .value_name("分支名")
-        .help("设置git远程分支名"))
-    .arg(Arg::with_name(ARG_GIT_PREFIX)
-        .long(ARG_GIT_PREFIX)
-        .value_name("url前缀")
-        .help("设置git的url前缀，例如 git@github.com/github.com/xxx"))
-    .arg(Arg::with_name(ARG_GIT_USERNAME)
-        .long(ARG_GIT_USERNAME)
-        .value_name("username")
-        .help("Sets username for git"))
-    .arg(Arg::with_name(ARG_GIT_PASSWORD)
-        .long(ARG_GIT_PASSWORD)
-        .value_name("password")
-        .help("Sets password for git"))
-    .arg(Arg::with_name(ARG_MAVEN_BIN)
-        .long(ARG_MAVEN_BIN)
-        .value_name(absolute_path)
-        .help("maven可执行文件路径"))
-    .arg(Arg::with_name(ARG_MAVEN_REPOSITORY)
-        .long(ARG_MAVEN_REPOSITORY)
-        .value_name(absolute_path)
-        .help("maven仓库目录"))
-    .arg(Arg::with_name(ARG_PACKAGE_ENV)
-        .long(ARG_PACKAGE_ENV)
-        .value_name("环境名")
-        .help("当前执行环境名,对应spring.profiles.active"))
-    .arg(Arg::with_name(ARG_PACKAGE_TARGET)
-        .long(ARG_PACKAGE_TARGET)
-        .value_name(relative_path)
-        .help("项目/模块内构建结果目录"))
-    .arg(Arg::with_name(ARG_DEPENDENCIES_UPDATE)
-        .long(ARG_DEPENDENCIES_UPDATE)
-        .value_name("依赖集合")
-        .required(false)
-        .help("项目所需要强制更新的依赖,采用gradle形式版本,\
-        多个依赖使用逗号隔开,形如:\n site.zido:demo:-1.0.1,site.zido:demo2:0.0.2"))
-    .arg(Arg::with_name(CONSTANTS_PROJECTS)
-        .value_name("项目名")
-        .required(true)
-        .multiple(true)
-        .help("设置发布项目名，支持多模块项目,多级项目情况下需要指定到具体模块名,\n形如: app/starter")))
-}
-
-#[cfg(not(test))]
-fn with_matches<'a, 'b>(app: clap::App<'a, 'b>) -> clap::ArgMatches<'a> {
-    app.get_matches()
+        Err(Error::Config(format!(
+            "配置文件错误:无法访问{}",
+            path.as_ref().display()
+        )))
+    }
 }
 
-#[cfg(test)]
-fn with_matches<'a, 'b>(app: clap::App<'a, 'b>) -> clap::ArgMatches<'a> {
-    app.get_matches_from(vec!["auto-deploy", "project-demo-2"])
+fn app<'a, 'b>() -> App<'a, 'b> {
+    let mut app = App::new("auto-deploy")
+        .version(crate_version!())
+        .author("zido. <wuhongxu1208@gmail.com>")
+        .about("自动发布项目到服务器，包含从git拉取->校验并构建项目->备份原版本->发布/回滚新版本")
+        // clap 的 --version 会直接输出到stdout，改为返回 Command::Help
+        .setting(AppSettings::DisableVersion)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name(ARG_VERSION)
+                .short("V")
+                .long(ARG_VERSION)
+                .help("Prints version information"),
+        )
+        .arg(
+            Arg::with_name(ARG_CONFIG)
+                .short("c")
+                .long(ARG_CONFIG)
+                .value_name("配置文件路径")
+                .takes_value(true)
+                .help("设置配置文件路径"),
+        )
+        .arg(
+            Arg::with_name(ARG_ENV)
+                .short("e")
+                .long(ARG_ENV)
+                .value_name("环境名")
+                .help("使用配置文件中 [env.<环境名>] 的环境配置,如: test/online"),
+        );
+    for key in KEYS {
+        let mut arg = Arg::with_name(key.arg)
+            .long(key.arg)
+            .value_name(key.value_name)
+            .takes_value(true)
+            .help(key.help);
+        if let Kind::Bool = key.kind {
+            arg = arg.possible_values(&["true", "false"]);
+        }
+        app = app.arg(arg);
+    }
+    app.arg(Arg::with_name(CONSTANTS_PROJECTS)
+        .value_name("项目名")
+        .multiple(true)
+        .help("设置发布项目名，支持多模块项目,多级项目情况下需要指定到具体模块名,\n形如: app/starter"))
+    .subcommand(SubCommand::with_name(SUBCOMMAND_ROLLBACK)
+        .about("列出项目在备份目录中的备份,并回滚到指定的备份")
+        .arg(Arg::with_name(ARG_ROLLBACK_GENERATION)
+            .short("g")
+            .long(ARG_ROLLBACK_GENERATION)
+            .value_name("序号")
+            .help("回滚到第几个备份,1为最新的备份,默认为1"))
+        .arg(Arg::with_name(ARG_ROLLBACK_LIST)
+            .short("l")
+            .long(ARG_ROLLBACK_LIST)
+            .help("只列出备份,不回滚"))
+        .arg(Arg::with_name(CONSTANTS_PROJECT)
+            .value_name("项目名")
+            .required(true)
+            .help("回滚的项目名,多级项目情况下需要指定到具体模块名,形如: app/starter")))
+    .subcommand(SubCommand::with_name(SUBCOMMAND_CONFIG)
+        .about("配置相关命令")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name(SUBCOMMAND_CONFIG_SHOW)
+            .about("显示最终生效的配置")
+            .arg(Arg::with_name(ARG_CONFIG_SHOW_ORIGIN)
+                .long(ARG_CONFIG_SHOW_ORIGIN)
+                .help("同时显示每项配置的来源: default/file/env/cli")))
+        .subcommand(SubCommand::with_name(SUBCOMMAND_CONFIG_VALIDATE)
+            .about("校验配置文件: 缺少/未知的配置项、目录是否可写、maven及java是否可执行、依赖格式"))
+        .subcommand(SubCommand::with_name(SUBCOMMAND_CONFIG_INIT)
+            .about("探测本机的java、maven及git配置,生成带注释的配置文件")
+            .arg(Arg::with_name(ARG_CONFIG_INIT_FORCE)
+                .short("f")
+                .long(ARG_CONFIG_INIT_FORCE)
+                .help("覆盖已存在的配置文件"))))
 }
 
 #[cfg(test)]
 mod test {
     use crate::config::*;
+    use crate::testing;
+
+    fn load(path: &str, args: &[&str], envs: &[(&str, &str)]) -> ConfigLoader {
+        let mut argv = vec!["auto-deploy", "-c", path];
+        argv.extend_from_slice(args);
+        ConfigLoader::new(path)
+            .args(argv)
+            .envs(envs.iter().map(|(k, v)| (*k, *v)))
+            .current_dir(env::temp_dir())
+    }
+
+    fn get_config(path: &str) -> Result<DeployConfig> {
+        load(path, &["project-demo-2"], &[]).load()
+    }
 
     #[test]
-    #[should_panic(expected = "配置文件错误:无法访问./xxx")]
-    fn get_config_from_toml_not_exists_should_panic() {
-        get_config_from_toml("./xxx");
+    fn get_config_from_toml_not_exists_should_return_err() {
+        let err = get_config_from_toml("./xxx").unwrap_err();
+        assert_eq!("config error: 配置文件错误:无法访问./xxx", err.to_string());
+        assert_eq!(2, err.exit_code());
+    }
+
+    #[test]
+    fn get_config_without_projects_should_return_err() {
+        let err = load("./example/example.toml", &[], &[]).load().unwrap_err();
+        assert_eq!(
+            "config error: need provide projects or run in a git repository",
+            err.to_string()
+        );
     }
 
     #[test]
-    #[should_panic]
     fn get_config_from_toml_missing_some_option_should_not_works() {
-        get_config("./example/missing_not_work.toml");
+        let err = get_config("./example/missing_not_work.toml").unwrap_err();
+        assert!(err.to_string().contains("missing field"));
+    }
+
+    #[test]
+    fn get_config_with_only_git_prefix_should_use_defaults() {
+        let layered = load(
+            "./example/minimal.toml",
+            &["demo"],
+            &[("HOME", "/home/zido")],
+        )
+        .load_layers()
+        .unwrap();
+        assert_eq!(Some(&Origin::Default), layered.origin("location.bin"));
+        let config = layered.into_config().unwrap();
+        assert_eq!("/home/zido/auto-deploy/projects", config.location.projects);
+        assert_eq!("/home/zido/auto-deploy/bin", config.location.bin);
+        assert_eq!("/home/zido/auto-deploy/logs", config.location.log);
+        assert_eq!("/home/zido/auto-deploy/bin/.temps", config.location.tmp);
+        assert_eq!("java", config.location.java);
+        assert_eq!("origin", config.git.remote);
+        assert_eq!("master", config.git.branch);
+        assert_eq!("git@github.com:zidoshare", config.git.prefix);
+        assert_eq!("mvn", config.maven.bin);
+        assert_eq!("/home/zido/.m2/repository", config.maven.repository);
+        assert_eq!("test", config.package.env);
+        assert_eq!("target", config.package.target);
+        assert!(config.dependencies.update.is_empty());
     }
 
     #[test]
     fn get_config_from_toml_missing_some_options_should_works() {
-        let config = get_config("./example/missing_works.toml");
+        let config = get_config("./example/missing_works.toml").unwrap();
         assert_eq!("test", config.package.env);
     }
 
@@ -320,7 +1245,7 @@ mod test {
 
     #[test]
     fn get_config_from_toml_works() {
-        let config = get_config("./example/example.toml");
+        let config = get_config("./example/example.toml").unwrap();
         assert_eq!("/home/zido/java/projects", config.location.projects);
         assert_eq!("/home/zido/java/bin", config.location.bin);
         assert_eq!("/home/zido/java/logs", config.location.log);
@@ -330,11 +1255,387 @@ mod test {
         assert_eq!("master", config.git.branch);
         assert_eq!("git@github.com/xxx", config.git.prefix);
         assert_eq!("wuhongxu1208@gmail.com", config.git.username.unwrap());
-        assert_eq!("xxx", config.git.password.unwrap());
+        assert_eq!("xxx", config.git.password.unwrap().expose());
         assert_eq!("mvn", config.maven.bin);
         assert_eq!("/home/zido/.m2/repository", config.maven.repository);
         assert_eq!("test", config.package.env);
         assert_eq!("target", config.package.target);
         assert_eq!("site.zido:demo:0.0.1", config.dependencies.update[0]);
     }
+
+    #[test]
+    fn env_overrides_file_and_cli_overrides_env() {
+        let envs = [
+            ("AUTO_DEPLOY_PACKAGE_ENV", "online"),
+            ("AUTO_DEPLOY_GIT_BRANCH", "online"),
+            ("AUTO_DEPLOY_BACKUP_KEEP", "3"),
+        ];
+        let args = ["--git-branch", "release", "project-demo-2"];
+        let layered = load("./example/missing_works.toml", &args, &envs)
+            .load_layers()
+            .unwrap();
+        assert_eq!(Some(&Origin::Default), layered.origin("maven.skip_tests"));
+        assert_eq!(
+            Some(&Origin::File(String::from("./example/missing_works.toml"))),
+            layered.origin("location.bin")
+        );
+        assert_eq!(
+            Some(&Origin::Env(String::from("AUTO_DEPLOY_PACKAGE_ENV"))),
+            layered.origin("package.env")
+        );
+        assert_eq!(
+            Some(&Origin::Cli(String::from("--git-branch"))),
+            layered.origin("git.branch")
+        );
+        let config = layered.into_config().unwrap();
+        assert_eq!("online", config.package.env);
+        assert_eq!("release", config.git.branch);
+        assert_eq!(Some(3), config.backup.keep);
+        assert_eq!(Some(true), config.maven.skip_tests);
+        assert_eq!(vec!["project-demo-2"], config.projects.unwrap());
+    }
+
+    #[test]
+    fn list_values_are_split_by_comma() {
+        let args = ["--dependencies-update", "a:b:1,c:d:2", "project-demo-2"];
+        let config = load("./example/example.toml", &args, &[]).load().unwrap();
+        assert_eq!(vec!["a:b:1", "c:d:2"], config.dependencies.update);
+    }
+
+    #[test]
+    fn invalid_number_should_return_err() {
+        let err = load(
+            "./example/example.toml",
+            &[],
+            &[("AUTO_DEPLOY_BACKUP_KEEP", "x")],
+        )
+        .load()
+        .unwrap_err();
+        assert_eq!(
+            "config error: backup.keep must be a number, found: x",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn config_show_with_origin() {
+        let layered = load(
+            "./example/example.toml",
+            &["config", "show", "--origin"],
+            &[],
+        )
+        .load_layers()
+        .unwrap();
+        assert_eq!(&Command::ConfigShow { origin: true }, layered.command());
+        let show = layered.show(true);
+        assert!(show.contains("package.env = \"test\"  # file ./example/example.toml\n"));
+        assert!(show.contains("startup.timeout = 120  # file ./example/example.toml\n"));
+        assert!(show.contains("maven.skip_tests = true  # file ./example/example.toml\n"));
+    }
+
+    #[test]
+    fn help_is_returned_instead_of_exiting() {
+        let layered = load("./example/example.toml", &["--help"], &[])
+            .load_layers()
+            .unwrap();
+        match layered.command() {
+            Command::Help(help) => assert!(help.contains("USAGE:")),
+            command => panic!("expect help, found: {:?}", command),
+        }
+        let layered = load("./example/example.toml", &["--version"], &[])
+            .load_layers()
+            .unwrap();
+        assert_eq!(
+            &Command::Help(format!("auto-deploy {}", env!("CARGO_PKG_VERSION"))),
+            layered.command()
+        );
+    }
+
+    #[test]
+    fn rollback_subcommand() {
+        let config = load(
+            "./example/example.toml",
+            &["rollback", "-g", "2", "demo"],
+            &[],
+        )
+        .load()
+        .unwrap();
+        assert_eq!(
+            Command::Rollback {
+                generation: 2,
+                list: false
+            },
+            config.command
+        );
+        assert_eq!(vec!["demo"], config.projects.unwrap());
+    }
+
+    #[test]
+    fn project_table_overrides_global_config() {
+        let args = ["demo", "/parent/child/", "other"];
+        let config = load("./example/example.toml", &args, &[]).load().unwrap();
+        let demo = config.project("demo");
+        assert_eq!("online", demo.git.branch);
+        assert_eq!("build", demo.package.target);
+        assert_eq!("/home/zido/java/bin", demo.location.bin);
+        assert_eq!(
+            Some(vec![String::from("-Xms256m"), String::from("-Xmx1g")]),
+            demo.startup.jvm_args
+        );
+        let child = config.project("parent/child");
+        assert_eq!("master", child.git.branch);
+        assert_eq!("/home/zido/java/child/bin", child.location.bin);
+        let other = config.project("other");
+        assert_eq!("master", other.git.branch);
+        assert_eq!("target", other.package.target);
+        assert_eq!(Some(vec![String::from("-Xmx512m")]), other.startup.jvm_args);
+    }
+
+    #[test]
+    fn cli_overrides_project_table() {
+        let args = ["--git-branch", "release", "demo"];
+        let config = load("./example/example.toml", &args, &[]).load().unwrap();
+        assert_eq!("release", config.project("demo").git.branch);
+        assert_eq!("build", config.project("demo").package.target);
+    }
+
+    #[test]
+    fn invalid_project_table_should_return_err() {
+        let args = ["--package-target", "build", "demo"];
+        let mut layered = load("./example/example.toml", &args, &[])
+            .load_layers()
+            .unwrap();
+        layered.projects.insert(
+            String::from("demo"),
+            vec![(String::from("backup.keep"), Value::from("x"))],
+        );
+        let err = layered.into_config().unwrap_err();
+        assert!(err.to_string().contains("projects.demo"));
+    }
+
+    #[test]
+    fn env_profile_overrides_global_config() {
+        let layered = load("./example/example.toml", &["--env", "online", "other"], &[])
+            .load_layers()
+            .unwrap();
+        assert_eq!(
+            Some(&Origin::Profile(String::from("online"))),
+            layered.origin("package.env")
+        );
+        let config = layered.into_config().unwrap();
+        assert_eq!("online", config.package.env);
+        assert_eq!("online", config.git.branch);
+        assert_eq!("/home/zido/java/online/bin", config.location.bin);
+        assert_eq!("/home/zido/java/online/logs", config.location.log);
+        assert_eq!("/home/zido/java/bin/.temps", config.location.tmp);
+        assert_eq!(vec!["site.zido:demo:1.0.0"], config.dependencies.update);
+    }
+
+    #[test]
+    fn env_profile_selected_by_env_var() {
+        let envs = [
+            ("AUTO_DEPLOY_ENV", "test"),
+            ("AUTO_DEPLOY_GIT_BRANCH", "hotfix"),
+        ];
+        let config = load("./example/example.toml", &["demo"], &envs)
+            .load()
+            .unwrap();
+        assert_eq!("test", config.package.env);
+        assert_eq!("hotfix", config.git.branch);
+        assert_eq!("/home/zido/java/bin", config.location.bin);
+        // [projects.demo] is more specific than [env.test]
+        assert_eq!("build", config.project("demo").package.target);
+    }
+
+    #[test]
+    fn unknown_env_profile_should_return_err() {
+        let err = load("./example/example.toml", &["-e", "dev", "demo"], &[])
+            .load()
+            .unwrap_err();
+        assert!(err.to_string().contains("[env.dev]"));
+        assert!(err.to_string().contains("online, test"));
+    }
+
+    fn temp_config(name: &str, contents: &str) -> String {
+        let dir = testing::temp_dir(&format!("config-{}", name));
+        let path = dir.join("config.toml");
+        fs::write(&path, contents.replace("{dir}", &dir.to_string_lossy())).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn secret_from_env_and_file() {
+        let path = temp_config(
+            "secret",
+            r#"
+[git]
+prefix = "https://github.com/zidoshare"
+password = { env = "GIT_TOKEN" }
+[projects.demo.git]
+password = { file = "{dir}/git.token" }
+"#,
+        );
+        let token = Path::new(&path).with_file_name("git.token");
+        fs::write(&token, "file-token\n").unwrap();
+        let layered = load(&path, &["demo", "other"], &[("GIT_TOKEN", "env-token")])
+            .load_layers()
+            .unwrap();
+        assert_eq!(
+            Some(&Origin::File(path.clone())),
+            layered.origin("git.password")
+        );
+        assert!(layered.show(false).contains("git.password = \"***\"\n"));
+        assert!(!layered.show(false).contains("token"));
+        let config = layered.into_config().unwrap();
+        let secret = |project: &str| {
+            config
+                .project(project)
+                .git
+                .password
+                .as_ref()
+                .unwrap()
+                .expose()
+                .to_owned()
+        };
+        assert_eq!("env-token", secret("other"));
+        assert_eq!("file-token", secret("demo"));
+        let debug = format!("{:#?}", config);
+        assert!(debug.contains("\"***\""));
+        assert!(!debug.contains("env-token") && !debug.contains("file-token"));
+    }
+
+    #[test]
+    fn credentials_by_host_and_project_url() {
+        let path = temp_config(
+            "credentials",
+            r#"
+[git]
+prefix = "git@github.com:zidoshare"
+[git.credentials."gitea.example.com"]
+username = "zido"
+token = { env = "GITEA_TOKEN" }
+[git.credentials."git@github.com:zidoshare"]
+ssh_key = "~/.ssh/github"
+[projects.demo]
+git.url = "https://gitea.example.com/zido/demo.git"
+"#,
+        );
+        let layered = load(&path, &["demo", "other"], &[("GITEA_TOKEN", "gitea-token")])
+            .load_layers()
+            .unwrap();
+        let show = layered.show(false);
+        assert!(show.contains("git.credentials.\"gitea.example.com\".token = \"***\"\n"));
+        assert!(show.contains("git.credentials.\"gitea.example.com\".username = \"zido\"\n"));
+        assert!(!show.contains("gitea-token"));
+        let config = layered.into_config().unwrap();
+        let credentials = &config.project("demo").git.credentials;
+        assert_eq!(
+            "gitea-token",
+            credentials["gitea.example.com"]
+                .token
+                .as_ref()
+                .unwrap()
+                .expose()
+        );
+        assert_eq!(
+            Some("~/.ssh/github"),
+            credentials["git@github.com:zidoshare"].ssh_key.as_deref()
+        );
+        assert_eq!(
+            Some("https://gitea.example.com/zido/demo.git"),
+            config.project("demo").git.url.as_deref()
+        );
+        assert_eq!(None, config.project("other").git.url);
+    }
+
+    #[test]
+    fn secret_from_missing_env_should_return_err() {
+        let path = temp_config(
+            "secret-missing",
+            "[git]\nprefix = \"x\"\npassword = { env = \"GIT_TOKEN\" }\n",
+        );
+        let err = load(&path, &["demo"], &[]).load().unwrap_err();
+        assert_eq!(
+            "config error: git.password: environment variable GIT_TOKEN is not set",
+            err.to_string()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn warn_world_readable_config_and_password_in_cli() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = temp_config("readable", "[git]\nprefix = \"x\"\n");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        let layered = load(&path, &["demo"], &[]).load_layers().unwrap();
+        assert!(layered.warnings().is_empty());
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let layered = load(&path, &["--git-password", "xxx", "demo"], &[])
+            .load_layers()
+            .unwrap();
+        assert_eq!(2, layered.warnings().len());
+        assert!(layered.warnings()[0].contains("chmod 600"));
+        assert!(layered.warnings()[1].contains("--git-password"));
+    }
+
+    #[test]
+    fn infer_project_and_branch_from_current_dir() {
+        let repo =
+            crate::git::test::repository("infer", "git@github.com:zidoshare/demo.git", "feature");
+        fs::write(repo.join("child/pom.xml"), "<project/>").unwrap();
+        let path = temp_config("infer", "[git]\nprefix = \"git@github.com:zidoshare\"\n");
+        let layered = load(&path, &[], &[])
+            .current_dir(repo.join("child"))
+            .load_layers()
+            .unwrap();
+        assert_eq!(
+            Some(&Origin::Repository(
+                repo.join("child").display().to_string()
+            )),
+            layered.origin("git.branch")
+        );
+        let config = layered.into_config().unwrap();
+        assert_eq!(vec!["demo/child"], config.projects.unwrap());
+        assert_eq!("feature", config.git.branch);
+
+        let config = load(&path, &["--git-branch", "test"], &[])
+            .current_dir(&repo)
+            .load()
+            .unwrap();
+        assert_eq!(vec!["demo"], config.projects.unwrap());
+        assert_eq!("test", config.git.branch);
+        assert_eq!(None, config.git.url);
+
+        let path = temp_config("infer-url", "[git]\nprefix = \"git@github.com:other\"\n");
+        let config = load(&path, &[], &[]).current_dir(&repo).load().unwrap();
+        assert_eq!(vec!["demo"], config.projects.unwrap());
+        assert_eq!(
+            Some("git@github.com:zidoshare/demo.git"),
+            config.git.url.as_deref()
+        );
+    }
+
+    #[test]
+    fn inferred_branch_does_not_override_chosen_env_profile() {
+        let repo = crate::git::test::repository(
+            "infer-profile",
+            "git@github.com:zidoshare/demo.git",
+            "feature",
+        );
+        let path = temp_config(
+            "infer-profile",
+            "[git]\nprefix = \"git@github.com:zidoshare\"\n[env.online]\ngit.branch = \"online\"\n",
+        );
+        let layered = load(&path, &["--env", "online"], &[])
+            .current_dir(&repo)
+            .load_layers()
+            .unwrap();
+        assert_eq!(
+            Some(&Origin::Profile(String::from("online"))),
+            layered.origin("git.branch")
+        );
+        let config = layered.into_config().unwrap();
+        assert_eq!(vec!["demo"], config.projects.unwrap());
+        assert_eq!("online", config.git.branch);
+    }
 }
@@ -0,0 +1,268 @@
+use super::validate::find_executable;
+use crate::backup;
+use crate::deploy;
+use crate::error::{Error, Result};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// values probed from the current machine to fill the generated config
+#[derive(Debug)]
+pub struct Probe {
+    pub home: PathBuf,
+    pub java: String,
+    pub maven: String,
+    pub repository: String,
+    // 当前目录所在git仓库的远程url前缀
+    pub prefix: Option<String>,
+}
+
+impl Probe {
+    /// probe java and mvn on `PATH`, `~/.m2/repository` and the remote of the git repository in the current directory,
+    /// the default paths are in the home directory, so it fails when the home directory is unknown
+    pub fn new() -> Result<Self> {
+        let home = dirs::home_dir().ok_or_else(|| {
+            Error::Config(String::from(
+                "cannot find the home directory for the default paths, set HOME",
+            ))
+        })?;
+        let program = |name: &str| {
+            find_executable(name)
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_else(|| String::from(name))
+        };
+        let repository = home.join(".m2/repository");
+        let prefix = env::current_dir()
+            .ok()
+            .and_then(|dir| git2::Repository::discover(dir).ok())
+            .and_then(|repo| {
+                let remote = repo.find_remote("origin").ok()?;
+                remote.url().and_then(remote_prefix)
+            });
+        Ok(Probe {
+            java: program("java"),
+            maven: program("mvn"),
+            repository: repository.to_string_lossy().into_owned(),
+            home,
+            prefix,
+        })
+    }
+}
+
+/// write a commented starter config to `path`, refuse to overwrite an existing file unless `force` is true
+pub fn init(path: &str, force: bool, probe: &Probe) -> Result<()> {
+    let path = Path::new(path);
+    if path.exists() && !force {
+        return Err(Error::Config(format!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        )));
+    }
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    write_private(path, &template(probe))?;
+    println!("config written to {}", path.display());
+    Ok(())
+}
+
+// the config may contain secrets, only the owner can read it
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // the mode is only used when the file is created
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+// the url prefix of a git remote: `git@github.com:zidoshare/auto-deploy.git` -> `git@github.com:zidoshare`
+fn remote_prefix(url: &str) -> Option<String> {
+    let url = url.trim_end_matches('/');
+    let (prefix, project) = url.split_at(url.rfind('/')?);
+    if prefix.is_empty() || prefix.ends_with('/') || project.len() <= 1 {
+        return None;
+    }
+    Some(String::from(prefix))
+}
+
+fn template(probe: &Probe) -> String {
+    let root = probe.home.join("auto-deploy");
+    let dir = |name: &str| root.join(name).to_string_lossy().into_owned();
+    let prefix = match &probe.prefix {
+        Some(prefix) => format!("prefix = \"{}\"", prefix),
+        None => String::from("prefix = \"git@github.com:xxx\" #未探测到git远程仓库,请修改"),
+    };
+    format!(
+        r#"###############
+# auto-deploy #
+###############
+#各项路径配置
+[location]
+projects = "{projects}" #项目所在目录
+bin = "{bin}" #项目可执行文件所在目录
+log = "{log}" #日志文件所在目录
+tmp = "{tmp}" #原可执行文件的备份文件所在目录
+java = "{java}" #java可执行文件路径
+
+#git相关配置
+[git]
+remote = "origin" #远程仓库名
+branch = "master" #远程分支名
+#项目的url前缀,项目url为 <prefix>/<项目名>.git
+{prefix}
+# ref = "v1.0.0" #发布指定的分支、tag或commit id,一般使用 --git-ref 参数指定
+single_branch = false #只克隆/拉取需要的分支或tag
+# depth = 1 #浅克隆的提交深度,需要系统安装git命令(2.9及以上)
+# name = "name"
+# email = "name@example.com"
+# username = "username" #https仓库的用户名
+# password = {{ file = "/etc/auto-deploy/git.password" }} #https仓库的密码,可引用环境变量或文件
+# token = {{ env = "GIT_TOKEN" }} #https仓库的访问令牌,优先于password,都未配置时使用 git credential fill
+use_agent = true #优先使用ssh-agent中的密钥
+# ssh_key = "~/.ssh/id_ed25519" #ssh私钥,默认依次尝试 ~/.ssh/id_ed25519、id_ecdsa、id_rsa
+# ssh_passphrase = {{ env = "SSH_PASSPHRASE" }} #ssh私钥的密码
+known_hosts = "{known_hosts}" #校验ssh主机密钥的known_hosts文件
+host_key_check = "strict" #主机密钥校验方式: strict / trust-on-first-use(记录首次连接的主机密钥) / off
+
+#maven相关配置
+[maven]
+bin = "{maven}" #maven可执行文件路径
+repository = "{repository}" #maven仓库目录
+skip_tests = true #默认跳过测试
+
+#打包相关配置
+[package]
+env = "test" #当前执行环境名,对应spring.profiles.active
+target = "target" #项目/模块内构建结果目录
+
+#依赖相关配置
+[dependencies]
+update = [] #强制更新的依赖,形如: ["site.zido:demo:0.0.1"]
+
+#备份相关配置
+[backup]
+keep = {keep} #每个项目保留的备份数量
+
+#启动监控相关配置,启动失败时自动回滚
+[startup]
+success = "{success}" #日志中出现此内容视为启动成功,*匹配同一行内的任意内容
+failure = [{failure}] #日志中出现其中任一内容视为启动失败
+timeout = {timeout} #启动超时时间(秒)
+jvm_args = [] #启动java时 -jar 前的JVM参数,如: ["-Xmx512m"]
+
+#环境配置,使用 --env <环境名> 选择,可覆盖以上任一配置,package.env 默认为环境名
+# [env.online]
+# git.branch = "online"
+
+#其他git服务器的认证信息,按项目的远程仓库url匹配host或url前缀,可配置 username/password/token/ssh_key/ssh_passphrase/use_agent
+# [git.credentials."gitea.example.com"]
+# token = {{ env = "GITEA_TOKEN" }}
+
+#各项目单独的配置,可覆盖以上任一配置,多级项目需要指定到具体模块名,如: [projects."parent/child"]
+# [projects.demo]
+# package.target = "build"
+# git.url = "https://gitea.example.com/zido/demo.git" #不在git.prefix下的项目的完整url
+"#,
+        projects = dir("projects"),
+        bin = dir("bin"),
+        log = dir("logs"),
+        tmp = dir("bin/.temps"),
+        java = probe.java,
+        prefix = prefix,
+        maven = probe.maven,
+        repository = probe.repository,
+        known_hosts = probe.home.join(".ssh/known_hosts").display(),
+        keep = backup::DEFAULT_KEEP,
+        success = deploy::DEFAULT_STARTUP_SUCCESS,
+        failure = deploy::DEFAULT_STARTUP_FAILURE
+            .iter()
+            .map(|s| format!("\"{}\"", s))
+            .collect::<Vec<_>>()
+            .join(", "),
+        timeout = deploy::DEFAULT_STARTUP_TIMEOUT_SECS,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::init::*;
+    use crate::config::ConfigLoader;
+    use crate::testing;
+
+    fn probe(prefix: Option<&str>) -> Probe {
+        Probe {
+            home: PathBuf::from("/home/zido"),
+            java: String::from("/usr/bin/java"),
+            maven: String::from("/usr/bin/mvn"),
+            repository: String::from("/home/zido/.m2/repository"),
+            prefix: prefix.map(String::from),
+        }
+    }
+
+    fn config_path(name: &str) -> String {
+        let dir = testing::temp_dir(&format!("init-{}", name));
+        dir.join("config.toml").to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn remote_prefix_of_urls() {
+        assert_eq!(
+            Some(String::from("git@github.com:zidoshare")),
+            remote_prefix("git@github.com:zidoshare/auto-deploy.git")
+        );
+        assert_eq!(
+            Some(String::from("https://github.com/zidoshare")),
+            remote_prefix("https://github.com/zidoshare/auto-deploy/")
+        );
+        assert_eq!(None, remote_prefix("git@github.com:auto-deploy.git"));
+    }
+
+    #[test]
+    fn init_writes_loadable_config() {
+        let path = config_path("loadable");
+        init(&path, false, &probe(Some("git@github.com:zidoshare"))).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+        let config = ConfigLoader::new(&path)
+            .args(vec!["auto-deploy", "demo"])
+            .envs(Vec::<(String, String)>::new())
+            .load()
+            .unwrap();
+        assert_eq!("/home/zido/auto-deploy/bin", config.location.bin);
+        assert_eq!("/usr/bin/java", config.location.java);
+        assert_eq!("git@github.com:zidoshare", config.git.prefix);
+        assert_eq!("/usr/bin/mvn", config.maven.bin);
+        assert_eq!("/home/zido/.m2/repository", config.maven.repository);
+        assert_eq!(Some(5), config.backup.keep);
+        assert!(config.dependencies.update.is_empty());
+    }
+
+    #[test]
+    fn init_refuses_to_overwrite_without_force() {
+        let path = config_path("overwrite");
+        init(&path, false, &probe(None)).unwrap();
+        let err = init(&path, false, &probe(None)).unwrap_err();
+        assert!(err.to_string().contains("--force"));
+        init(&path, true, &probe(Some("git@github.com:zidoshare"))).unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("prefix = \"git@github.com:zidoshare\""));
+    }
+}
@@ -0,0 +1,400 @@
+use super::{
+    flatten, Kind, LayeredConfig, Origin, CREDENTIAL_KEYS, CREDENTIAL_SECRETS, KEYS,
+    KEY_CREDENTIALS, KEY_ENV, KEY_PROJECTS,
+};
+use crate::projects;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::Value;
+
+// 没有默认值，必须配置的配置项
+static REQUIRED_KEYS: &[&str] = &["git.prefix"];
+// 发布时会自动创建这些目录，不存在时只是警告
+static LOCATION_DIRS: &[&str] = &[
+    "location.projects",
+    "location.bin",
+    "location.log",
+    "location.tmp",
+];
+static EXECUTABLES: &[&str] = &["maven.bin", "location.java"];
+
+#[derive(Debug, PartialEq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+/// a problem found in the config, with the line in the config file when known
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.level {
+            Level::Error => write!(f, "error")?,
+            Level::Warning => write!(f, "warning")?,
+        }
+        if let Some(line) = self.line {
+            write!(f, ": line {}", line)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// check the config file and the effective config:
+///
+/// * missing and unknown keys
+/// * `location.*` directories exist and are writable, missing directories are warnings as deploying creates them
+/// * `maven.bin` and `location.java` are executable
+/// * `dependencies.update` entries are `<groupId>:<artifactId>:<version>`
+///
+/// syntax errors are reported when the config is loaded.
+pub fn validate(layered: &LayeredConfig) -> Vec<Diagnostic> {
+    let contents = fs::read_to_string(layered.path()).unwrap_or_default();
+    let lines = key_lines(&contents);
+    let line_of = |path: &str| match layered.origin(path) {
+        Some(Origin::File(_)) => lines.get(path).cloned(),
+        Some(Origin::Profile(name)) => lines
+            .get(&format!("{}.{}.{}", KEY_ENV, name, path))
+            .cloned(),
+        _ => None,
+    };
+    let mut diagnostics: Vec<Diagnostic> = layered
+        .warnings()
+        .iter()
+        .map(|warning| Diagnostic {
+            level: Level::Warning,
+            line: None,
+            message: warning.clone(),
+        })
+        .collect();
+    let mut report = |level: Level, line: Option<usize>, message: String| {
+        diagnostics.push(Diagnostic {
+            level,
+            line,
+            message,
+        })
+    };
+
+    if let Ok(Value::Table(file)) = contents.parse::<Value>() {
+        let mut leaves = Vec::new();
+        flatten("", file, &mut leaves);
+        for (path, _) in leaves {
+            if !is_known_key(&path) {
+                report(
+                    Level::Error,
+                    lines.get(&path).cloned(),
+                    format!("unknown key `{}`", path),
+                );
+            }
+        }
+    }
+
+    let mut missing = false;
+    for path in REQUIRED_KEYS {
+        if layered.get(path).is_none() {
+            missing = true;
+            // 缺少配置项时指向其所在的表
+            let table = path.split('.').next().unwrap_or(path);
+            report(
+                Level::Error,
+                lines.get(table).cloned(),
+                format!("missing key `{}`", path),
+            );
+        }
+    }
+    if !missing {
+        if let Err(e) = layered.build() {
+            report(Level::Error, None, e.to_string());
+        }
+    }
+
+    for path in LOCATION_DIRS {
+        if let Some(Value::String(dir)) = layered.get(path) {
+            let dir = Path::new(dir);
+            if !dir.exists() {
+                report(
+                    Level::Warning,
+                    line_of(path),
+                    format!(
+                        "{}: directory {} does not exist, it is created when deploying",
+                        path,
+                        dir.display()
+                    ),
+                );
+            } else if let Err(message) = check_dir(dir) {
+                report(
+                    Level::Error,
+                    line_of(path),
+                    format!("{}: {}", path, message),
+                );
+            }
+        }
+    }
+    for path in EXECUTABLES {
+        if let Some(Value::String(program)) = layered.get(path) {
+            if find_executable(program).is_none() {
+                let message = if program.contains('/') {
+                    format!("{}: {} is not an executable file", path, program)
+                } else {
+                    format!("{}: {} is not found in PATH", path, program)
+                };
+                report(Level::Error, line_of(path), message);
+            }
+        }
+    }
+    if let Some(Value::Array(updates)) = layered.get("dependencies.update") {
+        for update in updates {
+            match update.as_str() {
+                Some(dependency) if projects::parse_dependency(dependency).is_some() => {}
+                _ => report(
+                    Level::Error,
+                    line_of("dependencies.update"),
+                    format!(
+                        "dependencies.update: {} is not <groupId>:<artifactId>:<version>",
+                        update
+                    ),
+                ),
+            }
+        }
+    }
+    diagnostics
+}
+
+/// the path of an executable file, `program` without `/` is searched in `PATH`
+pub(crate) fn find_executable(program: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return if is_executable(&path) {
+            Some(path)
+        } else {
+            None
+        };
+    }
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(|path| is_executable(path))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// [projects.<name>] 及 [env.<name>] 中可以配置任一全局配置项
+fn is_known_key(path: &str) -> bool {
+    // git.credentials 的host中可能包含`.`，只检查最后的配置项
+    if let Some(rest) = path.strip_prefix(&format!("git.{}.", KEY_CREDENTIALS)) {
+        let is_key = |key: &str| rest.ends_with(&format!(".{}", key));
+        return CREDENTIAL_KEYS.iter().any(|key| is_key(key))
+            || CREDENTIAL_SECRETS
+                .iter()
+                .any(|key| is_key(&format!("{}.env", key)) || is_key(&format!("{}.file", key)));
+    }
+    let mut parts = path.splitn(3, '.');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(table), Some(_), Some(key)) if table == KEY_PROJECTS || table == KEY_ENV => {
+            is_global_key(key)
+        }
+        _ => path == KEY_PROJECTS || is_global_key(path),
+    }
+}
+
+// 敏感信息还可以是 { env = "..." } 或 { file = "..." }
+fn is_global_key(path: &str) -> bool {
+    KEYS.iter().any(|k| {
+        k.path == path
+            || matches!(k.kind, Kind::Secret)
+                && (path == format!("{}.env", k.path) || path == format!("{}.file", k.path))
+    })
+}
+
+fn check_dir(dir: &Path) -> std::result::Result<(), String> {
+    if !dir.is_dir() {
+        return Err(format!("directory {} does not exist", dir.display()));
+    }
+    let probe = dir.join(format!(".auto-deploy-validate-{}", std::process::id()));
+    fs::write(&probe, "")
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|e| format!("directory {} is not writable: {}", dir.display(), e))
+}
+
+// the line number of each table header and key in a TOML file, keys are dotted paths
+fn key_lines(contents: &str) -> BTreeMap<String, usize> {
+    let mut lines = BTreeMap::new();
+    let mut table = String::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with("[[") {
+            continue;
+        }
+        if line.starts_with('[') {
+            if let Some(end) = line.find(']') {
+                table = dotted_key(&line[1..end]);
+                lines.insert(table.clone(), i + 1);
+            }
+        } else if let Some(eq) = line.find('=') {
+            let key = dotted_key(&line[..eq]);
+            if key.is_empty() || key.starts_with('#') {
+                continue;
+            }
+            let path = if table.is_empty() {
+                key
+            } else {
+                format!("{}.{}", table, key)
+            };
+            lines.insert(path, i + 1);
+        }
+    }
+    lines
+}
+
+// `a."b/c". d` -> `a.b/c.d`
+fn dotted_key(key: &str) -> String {
+    key.split('.')
+        .map(|part| part.trim().trim_matches(|c| c == '"' || c == '\''))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::validate::*;
+    use crate::config::ConfigLoader;
+    use crate::testing;
+
+    fn validate_toml(name: &str, contents: &str) -> Vec<String> {
+        let root = testing::temp_dir(&format!("validate-{}", name));
+        for dir in &["projects", "bin", "logs"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let path = root.join("config.toml");
+        let contents = contents.replace("{root}", &root.to_string_lossy());
+        fs::write(&path, contents).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        let path = path.to_string_lossy().into_owned();
+        let layered = ConfigLoader::new(&path)
+            .args(vec!["auto-deploy", "config", "validate"])
+            .envs(Vec::<(String, String)>::new())
+            .load_layers()
+            .unwrap();
+        let root = root.to_string_lossy();
+        validate(&layered)
+            .iter()
+            .map(|d| d.to_string().replace(root.as_ref(), "{root}"))
+            .collect()
+    }
+
+    #[test]
+    fn valid_config_has_no_diagnostics() {
+        let diagnostics = validate_toml(
+            "valid",
+            r#"
+[location]
+projects = "{root}/projects"
+bin = "{root}/bin"
+log = "{root}/logs"
+tmp = "{root}/bin"
+java = "/bin/sh"
+[git]
+remote = "origin"
+branch = "master"
+prefix = "git@github.com:zidoshare"
+[maven]
+bin = "sh"
+repository = "/tmp/repository"
+[package]
+env = "test"
+target = "target"
+[dependencies]
+update = ["site.zido:demo:0.0.1"]
+[git.credentials."gitea.example.com"]
+username = "zido"
+token = "token"
+[projects.demo]
+git.branch = "online"
+git.url = "https://gitea.example.com/zido/demo.git"
+"#,
+        );
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn invalid_config_reports_lines() {
+        let diagnostics = validate_toml(
+            "invalid",
+            r#"[location]
+projects = "{root}/not-exists"
+bin = "{root}/bin"
+log = "{root}/logs"
+tmp = "{root}/not-exists"
+java = "/not/exists/java"
+[git]
+remote = "origin"
+branch = "master"
+passwd = "xxx"
+[maven]
+bin = "sh"
+repository = "/tmp/repository"
+[dependencies]
+update = ["site.zido:demo:0.0.1", "site.zido:demo"]
+[git.credentials."gitea.example.com"]
+user = "zido"
+[projects.demo]
+git.brunch = "online"
+"#,
+        );
+        assert_eq!(
+            vec![
+                String::from("error: line 17: unknown key `git.credentials.gitea.example.com.user`"),
+                String::from("error: line 10: unknown key `git.passwd`"),
+                String::from("error: line 19: unknown key `projects.demo.git.brunch`"),
+                String::from("error: line 7: missing key `git.prefix`"),
+                String::from(
+                    "warning: line 2: location.projects: directory {root}/not-exists does not exist, it is created when deploying"
+                ),
+                String::from(
+                    "warning: line 5: location.tmp: directory {root}/not-exists does not exist, it is created when deploying"
+                ),
+                String::from(
+                    "error: line 6: location.java: /not/exists/java is not an executable file"
+                ),
+                String::from("error: line 15: dependencies.update: \"site.zido:demo\" is not <groupId>:<artifactId>:<version>"),
+            ],
+            diagnostics
+        );
+    }
+
+    #[test]
+    fn key_lines_of_tables_and_dotted_keys() {
+        let lines = key_lines(
+            "a = 1\n[git]\nbranch = \"x\" # comment\n[projects.\"parent/child\"]\ngit.branch = \"y\"\n",
+        );
+        assert_eq!(Some(&1), lines.get("a"));
+        assert_eq!(Some(&2), lines.get("git"));
+        assert_eq!(Some(&3), lines.get("git.branch"));
+        assert_eq!(Some(&4), lines.get("projects.parent/child"));
+        assert_eq!(Some(&5), lines.get("projects.parent/child.git.branch"));
+    }
+}
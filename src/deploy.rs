@@ -0,0 +1,552 @@
+use crate::backup::{self, Backup};
+use crate::config::{DeployConfig, StartupProps};
+use crate::error::{Error, Result};
+use crate::git::{self, Git};
+use crate::maven;
+use crate::projects;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static INVALID_END_PATH_VEC: &[char] = &['/', '\\'];
+// seconds to wait for the old process to exit after sending TERM
+static STOP_TIMEOUT_SECS: u64 = 30;
+// spring boot logs "Started Xxx in 3.2 seconds (JVM running for 3.9)" when started,
+// spring boot 3 logs "(process running for 3.9)" instead
+pub(crate) static DEFAULT_STARTUP_SUCCESS: &str = "Started * in * seconds";
+pub(crate) static DEFAULT_STARTUP_FAILURE: &[&str] =
+    &["APPLICATION FAILED TO START", "Application run failed"];
+pub(crate) static DEFAULT_STARTUP_TIMEOUT_SECS: u64 = 120;
+
+/// entry point of the deploy workflow: pull -> validate -> build -> backup -> deploy.
+///
+/// # Example:
+/// ```no_run
+/// use auto_deploy::{config, Deployer};
+///
+/// let config = config::get_config("/etc/auto-deploy/config.toml").unwrap();
+/// let deployer = Deployer::new(config);
+/// for (project, e) in deployer.deploy_all() {
+///     eprintln!("deploy project {} failed: {}", project, e);
+/// }
+/// ```
+pub struct Deployer {
+    config: DeployConfig,
+}
+
+impl Deployer {
+    pub fn new(config: DeployConfig) -> Self {
+        Deployer { config }
+    }
+
+    pub fn config(&self) -> &DeployConfig {
+        &self.config
+    }
+
+    /// deploy one project, `project` is the git project name,
+    /// multi-module projects need the starter module too, eg. `parent/child`.
+    ///
+    /// the project is deployed with its effective config, see [`DeployConfig::project`].
+    pub fn deploy(&self, project: &str) -> Result<()> {
+        deploy_project(self.config.project(project), project)
+    }
+
+    /// deploy every project in `config.projects` and continue when one of them failed,
+    /// returns the failed projects with their errors.
+    pub fn deploy_all(&self) -> Vec<(String, Error)> {
+        let mut failed = Vec::new();
+        for project in self.config.projects.iter().flatten() {
+            println!("deploy project {}...", project);
+            if let Err(e) = self.deploy(project) {
+                eprintln!("deploy project {} failed: {}", project, e);
+                failed.push((project.clone(), e));
+            }
+        }
+        failed
+    }
+
+    /// list the backups of a project and roll back to the chosen generation,
+    /// 1 is the latest backup. only list the backups when `list` is true.
+    pub fn rollback(&self, project: &str, generation: usize, list: bool) -> Result<()> {
+        rollback_project(self.config.project(project), project, generation, list)
+    }
+}
+
+fn deploy_project(config: &DeployConfig, project: &str) -> Result<()> {
+    let project = project.trim_matches(INVALID_END_PATH_VEC);
+    let projects_dir = Path::new(&config.location.projects);
+    let repository = repository_name(project);
+    let repository_path = projects_dir.join(repository);
+    fs::create_dir_all(projects_dir)?;
+    Git::new(&config.git).pull_projects(repository, &repository_path)?;
+    let commit = git::head_commit_id(&repository_path)?;
+
+    let project_path = projects_dir.join(project);
+    projects::validate_project(&project_path.to_string_lossy(), &config.package.env)?;
+    let updates = &config.dependencies.update;
+    projects::fix_dependencies(&project_path.join("pom.xml").to_string_lossy(), updates)?;
+    if project_path != repository_path {
+        projects::fix_dependencies(&repository_path.join("pom.xml").to_string_lossy(), updates)?;
+    }
+
+    maven::package(&config.maven, &project_path)?;
+
+    let package_name = package_name(project);
+    let jar = project_path
+        .join(&config.package.target)
+        .join(format!("{}.jar", package_name));
+    if !jar.exists() {
+        return Err(Error::Build(format!(
+            "build result {} is not exists",
+            jar.display()
+        )));
+    }
+
+    // location.bin has a default, so it may not exist on a fresh host
+    fs::create_dir_all(&config.location.bin)?;
+    release(config, package_name, &jar, &commit)
+}
+
+// replace the deployed jar with the built one and start it,
+// the previous jar is restored and started again when any step after stopping it fails
+fn release(config: &DeployConfig, package_name: &str, jar: &Path, commit: &str) -> Result<()> {
+    stop(config, package_name)?;
+    let keep = config.backup.keep.unwrap_or(backup::DEFAULT_KEEP);
+    let previous = match backup::backup(&config.location, keep, package_name) {
+        Ok(previous) => previous,
+        Err(e) => {
+            // the jar is still deployed when it cannot be moved
+            if backup::deployed_jar(&config.location, package_name).exists() {
+                if let Err(e) = start(config, package_name) {
+                    println!("restart {} failed: {}", package_name, e);
+                }
+            }
+            return Err(e.into());
+        }
+    };
+    let started = fs::copy(jar, backup::deployed_jar(&config.location, package_name))
+        .and_then(|_| {
+            backup::record_deploy(
+                &config.location,
+                package_name,
+                commit,
+                config.git.reference.as_deref(),
+            )
+        })
+        .map_err(Error::from)
+        .and_then(|_| start(config, package_name));
+    if let Err(e) = started {
+        println!("deploy {} failed: {}", package_name, e);
+        return match previous {
+            Some(previous) => {
+                rollback(config, package_name, &previous)?;
+                Err(Error::Deploy(format!(
+                    "deploy {} failed: {}, rolled back to {}",
+                    package_name, e, previous.commit
+                )))
+            }
+            None => Err(e),
+        };
+    }
+    Ok(())
+}
+
+fn rollback_project(
+    config: &DeployConfig,
+    project: &str,
+    generation: usize,
+    list: bool,
+) -> Result<()> {
+    let package_name = package_name(project.trim_matches(INVALID_END_PATH_VEC));
+    let mut backups = backup::list(&config.location, package_name)?;
+    if backups.is_empty() {
+        return Err(Error::Deploy(format!(
+            "no backup of {} in {}",
+            package_name, config.location.tmp
+        )));
+    }
+    backups.reverse();
+    let deployed = backup::deployed_commit(&config.location, package_name);
+    println!("backups of {}:", package_name);
+    for (i, backup) in backups.iter().enumerate() {
+        println!(
+            "{:>3}  {}  {}{}  {}{}",
+            i + 1,
+            backup::format_timestamp(backup.timestamp),
+            backup.commit,
+            backup
+                .git_ref
+                .as_ref()
+                .map(|git_ref| format!(" ({})", git_ref))
+                .unwrap_or_default(),
+            backup.path.display(),
+            if deployed.as_ref() == Some(&backup.commit) {
+                "  (deployed)"
+            } else {
+                ""
+            }
+        );
+    }
+    if list {
+        return Ok(());
+    }
+    let chosen = generation
+        .checked_sub(1)
+        .and_then(|i| backups.get(i))
+        .ok_or_else(|| {
+            Error::Deploy(format!(
+                "generation {} of {} is not exists",
+                generation, package_name
+            ))
+        })?;
+    println!("rollback {} to {}...", package_name, chosen.commit);
+    stop(config, package_name)?;
+    // back up the deployed jar to roll forward again, without removing the chosen backup
+    let keep = config
+        .backup
+        .keep
+        .unwrap_or(backup::DEFAULT_KEEP)
+        .max(generation + 1);
+    backup::backup(&config.location, keep, package_name)?;
+    backup::restore(&config.location, package_name, chosen)?;
+    start(config, package_name)
+}
+
+// stop the running process, restore the backup and start it.
+fn rollback(config: &DeployConfig, package_name: &str, backup: &Backup) -> Result<()> {
+    println!("rollback {} to {}...", package_name, backup.commit);
+    stop(config, package_name)?;
+    backup::restore(&config.location, package_name, backup)?;
+    start(config, package_name)
+}
+
+// the git repository of a project,eg. parent/child -> parent
+fn repository_name(project: &str) -> &str {
+    project
+        .split(INVALID_END_PATH_VEC)
+        .next()
+        .unwrap_or(project)
+}
+
+// the final package name of a project,eg. parent/child -> child
+fn package_name(project: &str) -> &str {
+    project
+        .rsplit(INVALID_END_PATH_VEC)
+        .next()
+        .unwrap_or(project)
+}
+
+fn pid_file(config: &DeployConfig, package_name: &str) -> PathBuf {
+    Path::new(&config.location.bin).join(format!("{}.pid", package_name))
+}
+
+// stop the running process recorded in the pid file
+fn stop(config: &DeployConfig, package_name: &str) -> Result<()> {
+    let pid_file = pid_file(config, package_name);
+    let pid = match fs::read_to_string(&pid_file) {
+        Ok(pid) => pid.trim().to_owned(),
+        Err(_) => return Ok(()),
+    };
+    if is_running(&pid) {
+        println!("stop {} (pid {})...", package_name, pid);
+        Command::new("kill").arg(&pid).status()?;
+        let mut waited = 0;
+        while is_running(&pid) {
+            if waited >= STOP_TIMEOUT_SECS {
+                return Err(Error::Deploy(format!(
+                    "cannot stop {} (pid {})",
+                    package_name, pid
+                )));
+            }
+            thread::sleep(Duration::from_secs(1));
+            waited += 1;
+        }
+    }
+    fs::remove_file(&pid_file)?;
+    Ok(())
+}
+
+// zombie processes are not running
+fn is_running(pid: &str) -> bool {
+    Command::new("ps")
+        .args(["-o", "stat=", "-p", pid])
+        .output()
+        .map(|output| {
+            let stat = String::from_utf8_lossy(&output.stdout);
+            output.status.success() && !stat.trim_start().starts_with('Z')
+        })
+        .unwrap_or(false)
+}
+
+// start the deployed jar and watch its log until it is started,
+// the process is killed when it fails to start
+fn start(config: &DeployConfig, package_name: &str) -> Result<()> {
+    fs::create_dir_all(&config.location.log)?;
+    let log_path = Path::new(&config.location.log).join(format!("{}.log", package_name));
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)?;
+    let offset = log.metadata()?.len();
+    let mut child = Command::new(&config.location.java)
+        .args(config.startup.jvm_args.iter().flatten())
+        .arg("-jar")
+        .arg(backup::deployed_jar(&config.location, package_name))
+        .current_dir(&config.location.bin)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .spawn()?;
+    println!(
+        "start {} (pid {}), log: {}",
+        package_name,
+        child.id(),
+        log_path.display()
+    );
+    fs::write(pid_file(config, package_name), child.id().to_string())?;
+    if let Err(e) = watch(&config.startup, &mut child, &log_path, offset) {
+        let _ = child.kill();
+        let _ = child.wait();
+        let _ = fs::remove_file(pid_file(config, package_name));
+        return Err(e);
+    }
+    println!("{} started", package_name);
+    Ok(())
+}
+
+// watch the log written since `offset` for the success or failure markers,
+// `*` in a marker matches any text within a line
+fn watch(startup: &StartupProps, child: &mut Child, log_path: &Path, offset: u64) -> Result<()> {
+    let success = startup
+        .success
+        .as_deref()
+        .unwrap_or(DEFAULT_STARTUP_SUCCESS);
+    let failure: Vec<&str> = match startup.failure {
+        Some(ref failure) => failure.iter().map(String::as_str).collect(),
+        None => DEFAULT_STARTUP_FAILURE.to_vec(),
+    };
+    let timeout = startup.timeout.unwrap_or(DEFAULT_STARTUP_TIMEOUT_SECS);
+    let begin = Instant::now();
+    let mut log = File::open(log_path)?;
+    log.seek(SeekFrom::Start(offset))?;
+    let mut output = String::new();
+    loop {
+        let mut buf = Vec::new();
+        log.read_to_end(&mut buf)?;
+        output.push_str(&String::from_utf8_lossy(&buf));
+        if let Some(marker) = failure
+            .iter()
+            .find(|marker| contains_marker(&output, marker))
+        {
+            return Err(Error::Deploy(format!("found \"{}\" in log", marker)));
+        }
+        if contains_marker(&output, success) {
+            return Ok(());
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(Error::Deploy(format!("process exited: {}", status)));
+        }
+        if begin.elapsed() >= Duration::from_secs(timeout) {
+            return Err(Error::Deploy(format!("not started in {} seconds", timeout)));
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+fn contains_marker(output: &str, marker: &str) -> bool {
+    if !marker.contains('*') {
+        return output.contains(marker);
+    }
+    output.lines().any(|line| {
+        let mut rest = line;
+        marker.split('*').all(|part| match rest.find(part) {
+            Some(i) => {
+                rest = &rest[i + part.len()..];
+                true
+            }
+            None => false,
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::deploy::*;
+    use crate::testing;
+
+    #[test]
+    fn repository_name_of_multi_module_project_is_parent() {
+        assert_eq!("parent", repository_name("parent/child"));
+        assert_eq!("demo", repository_name("demo"));
+    }
+
+    #[test]
+    fn package_name_of_multi_module_project_is_child() {
+        assert_eq!("child", package_name("parent/child"));
+        assert_eq!("demo", package_name("demo"));
+    }
+
+    #[test]
+    fn startup_markers_with_wildcards() {
+        let boot2 = "INFO Started DemoApplication in 3.2 seconds (JVM running for 3.9)";
+        let boot3 = "INFO Started DemoApplication in 3.2 seconds (process running for 3.9)";
+        assert!(contains_marker(boot2, DEFAULT_STARTUP_SUCCESS));
+        assert!(contains_marker(boot3, DEFAULT_STARTUP_SUCCESS));
+        assert!(contains_marker(boot3, "process running for"));
+        assert!(!contains_marker(
+            "Started DemoApplication\nin 3.2 seconds",
+            DEFAULT_STARTUP_SUCCESS
+        ));
+        assert!(!contains_marker(
+            "Tomcat started on port(s): 8080",
+            DEFAULT_STARTUP_SUCCESS
+        ));
+    }
+
+    // a fake java: starts with `startup.jvm_args` when the jar contains "good", fails to start otherwise
+    #[cfg(unix)]
+    fn config(name: &str) -> DeployConfig {
+        use std::os::unix::fs::PermissionsExt;
+        let root = testing::temp_dir(&format!("deploy-{}", name));
+        fs::create_dir_all(root.join("bin")).unwrap();
+        let java = root.join("java");
+        fs::write(
+            &java,
+            "#!/bin/sh\n\
+             if [ \"$1 $2\" = '-Xmx64m -jar' ] && [ \"$(cat \"$3\")\" = good ]; then echo 'Started Demo in 1.2 seconds (process running for 1.5)';\n\
+             else echo '***************************'; echo 'APPLICATION FAILED TO START'; fi\n\
+             exec sleep 30\n",
+        )
+        .unwrap();
+        fs::set_permissions(&java, fs::Permissions::from_mode(0o755)).unwrap();
+        toml::from_str(&format!(
+            r#"
+            [location]
+            projects = "{root}/projects"
+            bin = "{root}/bin"
+            log = "{root}/logs"
+            tmp = "{root}/bin/.temps"
+            java = "{root}/java"
+            [git]
+            remote = "origin"
+            branch = "master"
+            prefix = "git@github.com:zidoshare"
+            [maven]
+            bin = "mvn"
+            repository = "{root}/repository"
+            [package]
+            env = "test"
+            target = "target"
+            [dependencies]
+            update = []
+            [startup]
+            timeout = 10
+            jvm_args = ["-Xmx64m"]
+            "#,
+            root = root.display()
+        ))
+        .unwrap()
+    }
+
+    #[cfg(unix)]
+    fn deploy(config: &DeployConfig, content: &str) {
+        fs::write(backup::deployed_jar(&config.location, "demo"), content).unwrap();
+        backup::record_deploy(&config.location, "demo", content, None).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn start_success_when_log_contains_success_marker() {
+        let config = config("start-success");
+        deploy(&config, "good");
+        start(&config, "demo").unwrap();
+        assert!(pid_file(&config, "demo").exists());
+        stop(&config, "demo").unwrap();
+        assert!(!pid_file(&config, "demo").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn start_failed_when_log_contains_failure_marker() {
+        let config = config("start-failed");
+        deploy(&config, "bad");
+        let err = start(&config, "demo").unwrap_err();
+        assert!(err.to_string().contains("APPLICATION FAILED TO START"));
+        assert!(!pid_file(&config, "demo").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn rollback_restores_backup_and_starts_it() {
+        let config = config("rollback");
+        deploy(&config, "good");
+        let previous = backup::backup(&config.location, backup::DEFAULT_KEEP, "demo")
+            .unwrap()
+            .unwrap();
+        deploy(&config, "bad");
+        assert!(start(&config, "demo").is_err());
+        rollback(&config, "demo", &previous).unwrap();
+        let deployed = fs::read_to_string(backup::deployed_jar(&config.location, "demo")).unwrap();
+        assert_eq!("good", deployed);
+        stop(&config, "demo").unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn release_restores_previous_jar_when_copy_failed() {
+        let config = config("release-copy-failed");
+        deploy(&config, "good");
+        start(&config, "demo").unwrap();
+        let missing = Path::new(&config.location.projects).join("demo.jar");
+        let err = release(&config, "demo", &missing, "bad").unwrap_err();
+        assert!(err.to_string().contains("rolled back to good"));
+        let deployed = fs::read_to_string(backup::deployed_jar(&config.location, "demo")).unwrap();
+        assert_eq!("good", deployed);
+        assert!(pid_file(&config, "demo").exists());
+        stop(&config, "demo").unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn rollback_project_backs_up_deployed_jar_to_roll_forward() {
+        let mut config = config("roll-forward");
+        config.backup.keep = Some(1);
+        deploy(&config, "good");
+        backup::backup(&config.location, backup::DEFAULT_KEEP, "demo").unwrap();
+        deploy(&config, "bad");
+        rollback_project(&config, "demo", 1, false).unwrap();
+        stop(&config, "demo").unwrap();
+        let commits: Vec<String> = backup::list(&config.location, "demo")
+            .unwrap()
+            .into_iter()
+            .map(|b| b.commit)
+            .collect();
+        assert_eq!(vec!["good", "bad"], commits);
+        assert_eq!(
+            Some(String::from("good")),
+            backup::deployed_commit(&config.location, "demo")
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn rollback_project_to_chosen_generation() {
+        let config = config("rollback-project");
+        deploy(&config, "good");
+        backup::backup(&config.location, backup::DEFAULT_KEEP, "demo").unwrap();
+        deploy(&config, "bad");
+        backup::backup(&config.location, backup::DEFAULT_KEEP, "demo").unwrap();
+        assert!(rollback_project(&config, "demo", 3, false).is_err());
+        rollback_project(&config, "demo", 1, true).unwrap();
+        assert!(!backup::deployed_jar(&config.location, "demo").exists());
+        rollback_project(&config, "demo", 2, false).unwrap();
+        assert_eq!(
+            Some(String::from("good")),
+            backup::deployed_commit(&config.location, "demo")
+        );
+        stop(&config, "demo").unwrap();
+    }
+}
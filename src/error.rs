@@ -0,0 +1,85 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// errors of the deploy workflow, each kind has its own exit code
+#[derive(Debug)]
+pub enum Error {
+    // bad config file or command line arguments
+    Config(String),
+    Git(git2::Error),
+    Xml(quick_xml::Error),
+    Io(io::Error),
+    // the project does not match the convention, eg. missing application-${env}.properties
+    Validation(String),
+    // maven build failed
+    Build(String),
+    // the deployed jar failed to start or cannot be rolled back
+    Deploy(String),
+}
+
+impl Error {
+    /// the exit code of the process when deploy failed with this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Git(_) => 3,
+            Error::Xml(_) => 4,
+            Error::Io(_) => 5,
+            Error::Validation(_) => 6,
+            Error::Build(_) => 7,
+            Error::Deploy(_) => 8,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(e) => write!(f, "config error: {}", e),
+            Error::Git(e) => write!(f, "git error: {}", e),
+            Error::Xml(e) => write!(f, "xml error: {}", e),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Validation(e) => write!(f, "validation error: {}", e),
+            Error::Build(e) => write!(f, "build error: {}", e),
+            Error::Deploy(e) => write!(f, "deploy error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Git(e) => Some(e),
+            Error::Xml(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<git2::Error> for Error {
+    fn from(e: git2::Error) -> Self {
+        Error::Git(e)
+    }
+}
+
+impl From<quick_xml::Error> for Error {
+    fn from(e: quick_xml::Error) -> Self {
+        Error::Xml(e)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Error::Config(e.to_string())
+    }
+}
@@ -1,30 +1,26 @@
-use crate::config;
-use git2;
+use crate::config::{self, HostKeyCheck};
 use std::env;
 use std::io::{self, Write};
 use std::path;
-use std::str;
+use std::process::Command;
 
-struct Git<'a> {
+pub mod known_hosts;
+
+pub struct Git<'a> {
     //save user git cred
-    cred: Option<git2::Cred>,
+    cred: Option<UserPass>,
+    #[allow(dead_code)]
     signature: Option<git2::Signature<'static>>,
     config: &'a config::GitProps,
 }
 
 impl<'a> Git<'a> {
     pub fn new(config: &'a config::GitProps) -> Self {
-        let cred: Option<git2::Cred> = if config.username.is_some() && config.password.is_some() {
-            Some(
-                git2::Cred::userpass_plaintext(
-                    config.username.as_ref().unwrap(),
-                    config.password.as_ref().unwrap(),
-                )
-                .unwrap(),
-            )
-        } else {
-            None
-        };
+        let cred = UserPass::new(
+            config.username.as_ref(),
+            config.token.as_ref(),
+            config.password.as_ref(),
+        );
         Git {
             cred,
             signature: None,
@@ -32,50 +28,102 @@ impl<'a> Git<'a> {
         }
     }
     //need provide git project and local project path
-    #[allow(dead_code)]
+    //
+    // the project is checked out at `git.ref` (detached) when it is set,
+    // otherwise at the head of `<git.remote>/<git.branch>`.
     pub fn pull_projects<'b>(
         &self,
         project: &'b str,
         local_project_path: &'b std::path::Path,
     ) -> Result<(), git2::Error> {
-        let remote_git_path = format!("{}/{}.git", self.config.prefix, project);
+        let remote_git_path = match &self.config.url {
+            Some(url) => url.clone(),
+            None => remote_url(&self.config.prefix, project)?,
+        };
         println!("remote git path:{}", remote_git_path);
-        let mut callbacks = git2::RemoteCallbacks::new();
-        callbacks.credentials(|_user: &str, _user_from_url: Option<&str>, _cred| {
-            if _cred.contains(git2::CredentialType::USERNAME) {
-                return git2::Cred::userpass_plaintext(
-                    self.config
-                        .username
-                        .as_ref()
-                        .expect("the git server need provide username"),
-                    self.config
-                        .password
-                        .as_ref()
-                        .expect("the git server need provide password"),
+        if local_project_path.exists() {
+            let repo = git2::Repository::open(local_project_path)?;
+            // git.url or git.prefix may have changed since the project was cloned
+            let stored = repo
+                .find_remote(&self.config.remote)
+                .ok()
+                .map(|remote| remote.url().map(String::from));
+            if let Some(stored) = stored.filter(|url| url.as_ref() != Some(&remote_git_path)) {
+                println!(
+                    "change url of remote {} from {} to {}",
+                    self.config.remote,
+                    stored.unwrap_or_default(),
+                    remote_git_path
                 );
+                repo.remote_set_url(&self.config.remote, &remote_git_path)?;
+            }
+            self.fetch(&repo, &remote_git_path)?;
+            self.checkout(&repo)
+        } else if self.partial() || self.config.reference.is_some() {
+            // only fetch what is needed instead of cloning all branches
+            let repo = git2::Repository::init(local_project_path)?;
+            repo.remote(&self.config.remote, &remote_git_path)?;
+            if self.partial() {
+                repo.config()?.set_str(
+                    &format!("remote.{}.fetch", self.config.remote),
+                    &self.branch_refspec(&self.config.branch),
+                )?;
             }
+            self.fetch(&repo, &remote_git_path)?;
+            self.checkout(&repo)
+        } else {
+            let mut opts = git2::FetchOptions::new();
+            opts.remote_callbacks(self.remote_callbacks(&remote_git_path));
+            opts.download_tags(git2::AutotagOption::None);
 
-            let user = _user_from_url.unwrap_or("git");
-            match env::var("AUTO_DEPLOY_SSH_KEY") {
-                Ok(k) => {
-                    println!(
-                        "authenticate with user {} and private key located in {}",
-                        user, k
-                    );
-                    git2::Cred::ssh_key(user, None, path::Path::new(&k), None)
-                }
-                _ => {
-                    let mut ssh_path = env::home_dir().unwrap();
-                    ssh_path.push(".ssh/id_rsa");
-                    git2::Cred::ssh_key(user, None, &ssh_path, None)
-                    //     Err(git2::Error::from_str(
-                    //     "unable to get private key from AUTO_DEPLOY_SSH_KEY and",
-                    // ))
-                }
+            let mut builder = git2::build::RepoBuilder::new();
+            builder.fetch_options(opts);
+            builder.branch(&self.config.branch);
+            builder.remote_create(|repo, _, url| repo.remote(&self.config.remote, url));
+
+            let repo = builder.clone(&remote_git_path, local_project_path)?;
+            let head = repo.head()?.target();
+            if let Some(head) = head {
+                println!("cloned {} at {}", self.config.branch, head);
+            }
+            Ok(())
+        }
+    }
+
+    // `git.depth` or `git.single_branch` is configured
+    fn partial(&self) -> bool {
+        self.config.depth.is_some() || self.config.single_branch.unwrap_or(false)
+    }
+
+    fn remote_callbacks(&self, url: &str) -> git2::RemoteCallbacks<'_> {
+        let mut callbacks = git2::RemoteCallbacks::new();
+        let check = self.config.host_key_check.unwrap_or(HostKeyCheck::Strict);
+        // only ssh host keys are checked, https certificates are verified by libgit2
+        if let (Some(port), false) = (ssh_port(url), check == HostKeyCheck::Off) {
+            callbacks.certificate_check(move |cert, host| self.check_host_key(cert, host, port));
+        }
+        let credential = self.credential(url);
+        let username =
+            credential.map_or(self.config.username.as_deref(), |c| c.username.as_deref());
+        let (ssh_key, passphrase, use_agent) = self.ssh_key(url);
+        let mut ssh = SshAuth::new(
+            ssh_key,
+            passphrase,
+            use_agent,
+            dirs::home_dir(),
+            env::var_os("SSH_AUTH_SOCK").is_some(),
+        );
+        let mut http = HttpAuth::new(self.user_pass(url));
+        callbacks.credentials(move |url: &str, user_from_url: Option<&str>, allowed| {
+            let user = user_from_url.or(username);
+            match auth_method(url, allowed) {
+                AuthMethod::Http => http.next(url, user),
+                AuthMethod::Username => git2::Cred::username(user.unwrap_or("git")),
+                AuthMethod::Ssh => ssh.next(user.unwrap_or("git")),
             }
         });
         callbacks.sideband_progress(|data| {
-            print!("remote:{}", str::from_utf8(data).unwrap());
+            print!("remote:{}", String::from_utf8_lossy(data));
             io::stdout().flush().unwrap();
             true
         });
@@ -108,57 +156,1270 @@ impl<'a> Git<'a> {
             io::stdout().flush().unwrap();
             true
         });
+        callbacks
+    }
 
-        if local_project_path.exists() {
-            let repo = git2::Repository::open(local_project_path)?;
-            let mut remote = repo
-                .find_remote(&self.config.remote)
-                .or_else(|_| (&repo).remote_anonymous(&self.config.remote))?;
-            let mut fo = git2::FetchOptions::new();
-            fo.remote_callbacks(callbacks);
-            remote.download(&[], Some(&mut fo))?;
-            let stats = remote.stats();
+    // the `git.credentials` entry of a remote url: the longest matching url prefix,
+    // or the entry of its host
+    fn credential(&self, url: &str) -> Option<&config::CredentialProps> {
+        let host = url_host(url);
+        self.config
+            .credentials
+            .iter()
+            .filter_map(|(key, credential)| {
+                if is_url_prefix(key, url) {
+                    Some((key.len(), credential))
+                } else if host
+                    .as_ref()
+                    .is_some_and(|host| host.eq_ignore_ascii_case(key))
+                {
+                    Some((0, credential))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, credential)| credential)
+    }
 
-            if stats.local_objects() > 0 {
-                println!(
-                    "\rReceived {}/{} objects in {} bytes (used {} local \
+    // the https credentials of a remote url, from `git.credentials` or the global config
+    fn user_pass(&self, url: &str) -> Option<UserPass> {
+        match self.credential(url) {
+            Some(c) => UserPass::new(c.username.as_ref(), c.token.as_ref(), c.password.as_ref()),
+            None => self.cred.clone(),
+        }
+    }
 
-                                                                     objects)",
-                    stats.indexed_objects(),
-                    stats.total_objects(),
-                    stats.received_bytes(),
-                    stats.local_objects()
+    // the ssh key, passphrase and `use_agent` of a remote url, from `git.credentials` or the global config
+    fn ssh_key(&self, url: &str) -> (Option<&str>, Option<&config::Secret>, Option<bool>) {
+        match self.credential(url) {
+            Some(c) => (c.ssh_key.as_deref(), c.ssh_passphrase.as_ref(), c.use_agent),
+            None => (
+                self.config.ssh_key.as_deref(),
+                self.config.ssh_passphrase.as_ref(),
+                self.config.use_agent,
+            ),
+        }
+    }
+
+    // the ssh host key must be in `git.known_hosts`, unknown keys are added to it in
+    // trust-on-first-use mode, and a changed key is always rejected
+    fn check_host_key(&self, cert: &git2::cert::Cert, host: &str, port: u16) -> bool {
+        let sha1 = match cert.as_hostkey().and_then(|key| key.hash_sha1()) {
+            Some(sha1) => sha1,
+            None => {
+                eprintln!("cannot check the host key of {}: not a ssh host key", host);
+                return false;
+            }
+        };
+        let path = match &self.config.known_hosts {
+            Some(path) => path::Path::new(path),
+            None => {
+                eprintln!(
+                    "cannot check the host key of {}: git.known_hosts is not set",
+                    host
                 );
-            } else {
-                println!(
-                    "\rReceived {}/{} objects in {} bytes",
-                    stats.indexed_objects(),
-                    stats.total_objects(),
-                    stats.received_bytes()
+                return false;
+            }
+        };
+        let fingerprint = known_hosts::fingerprint(sha1);
+        match known_hosts::check(path, host, port, sha1) {
+            Ok(known_hosts::Check::Match) => true,
+            Ok(known_hosts::Check::Unknown)
+                if self.config.host_key_check == Some(HostKeyCheck::TrustOnFirstUse) =>
+            {
+                match known_hosts::record(path, host, port, sha1) {
+                    Ok(()) => {
+                        println!(
+                            "permanently added the host key {} of {} to {}",
+                            fingerprint,
+                            host,
+                            path.display()
+                        );
+                        true
+                    }
+                    Err(e) => {
+                        eprintln!("cannot add the host key of {}: {}", host, e);
+                        false
+                    }
+                }
+            }
+            Ok(known_hosts::Check::Unknown) => {
+                eprintln!(
+                    "the host key {} of {} is not in {}, add it with `ssh-keyscan -p {} {} >> {}` \
+                     or set git.host_key_check = \"trust-on-first-use\"",
+                    fingerprint,
+                    host,
+                    path.display(),
+                    port,
+                    host,
+                    path.display()
                 );
+                false
+            }
+            Ok(known_hosts::Check::Mismatch(known)) => {
+                // libssh2 may prefer another key type than the known keys of the host,
+                // only a different key of a known type is a changed host key
+                match known_hosts::key_type(host, port, sha1) {
+                    Ok(key_type) if !known.contains(&key_type) => eprintln!(
+                        "no {} key of {} is known in {} (known: {}), add the host key {} with \
+                         `ssh-keyscan -t {} -p {} {} >> {}`",
+                        key_type,
+                        host,
+                        path.display(),
+                        known.join(", "),
+                        fingerprint,
+                        key_type,
+                        port,
+                        host,
+                        path.display()
+                    ),
+                    _ => eprintln!(
+                        "WARNING: the host key {} of {} does not match the key in {}, \
+                         someone could be eavesdropping on you (man-in-the-middle attack)",
+                        fingerprint,
+                        host,
+                        path.display()
+                    ),
+                }
+                false
+            }
+            Ok(known_hosts::Check::Revoked) => {
+                eprintln!("the host key {} of {} is revoked", fingerprint, host);
+                false
+            }
+            Err(e) => {
+                eprintln!("cannot read {}: {}", path.display(), e);
+                false
+            }
+        }
+    }
+
+    // fetch `git.branch`, or all branches and tags when `git.ref` is set.
+    //
+    // in partial mode only the ref itself is fetched, as a branch, a tag or a commit id,
+    // and all branches and tags are fetched when it is not reachable in this way.
+    fn fetch(&self, repo: &git2::Repository, remote_git_path: &str) -> Result<(), git2::Error> {
+        let reference = match &self.config.reference {
+            Some(reference) => reference,
+            None => {
+                let refspec = self.branch_refspec(&self.config.branch);
+                return self.fetch_refspecs(repo, remote_git_path, &[refspec], false);
+            }
+        };
+        if self.partial() {
+            let refspecs = vec![
+                self.branch_refspec(reference),
+                format!("+refs/tags/{0}:refs/tags/{0}", reference),
+                reference.clone(),
+            ];
+            for refspec in refspecs {
+                if self
+                    .fetch_refspecs(repo, remote_git_path, &[refspec], false)
+                    .and_then(|_| self.resolve(repo, reference))
+                    .is_ok()
+                {
+                    return Ok(());
+                }
             }
-            remote.disconnect();
-            remote.update_tips(None, true, git2::AutotagOption::Unspecified, None)?;
+            println!(
+                "{} is not reachable by a partial fetch, fetch all branches and tags",
+                reference
+            );
+        }
+        let refspecs = vec![
+            format!("+refs/heads/*:refs/remotes/{}/*", self.config.remote),
+            String::from("+refs/tags/*:refs/tags/*"),
+        ];
+        self.fetch_refspecs(repo, remote_git_path, &refspecs, true)
+    }
+
+    fn branch_refspec(&self, branch: &str) -> String {
+        format!(
+            "+refs/heads/{0}:refs/remotes/{1}/{0}",
+            branch, self.config.remote
+        )
+    }
+
+    // fetch with the git command when `git.depth` is set, libgit2 does not support shallow fetches
+    fn fetch_refspecs(
+        &self,
+        repo: &git2::Repository,
+        remote_git_path: &str,
+        refspecs: &[String],
+        all: bool,
+    ) -> Result<(), git2::Error> {
+        if let Some(depth) = self.config.depth {
+            return self.shallow_fetch(repo, remote_git_path, refspecs, depth, all);
+        }
+        let mut remote = repo
+            .find_remote(&self.config.remote)
+            .or_else(|_| repo.remote_anonymous(remote_git_path))?;
+        let mut fo = git2::FetchOptions::new();
+        let url = remote.url().unwrap_or(remote_git_path).to_owned();
+        fo.remote_callbacks(self.remote_callbacks(&url));
+        if all {
+            fo.download_tags(git2::AutotagOption::All);
+        } else if self.config.reference.is_some() {
+            fo.download_tags(git2::AutotagOption::None);
+        }
+        let refspecs: Vec<&str> = refspecs.iter().map(String::as_str).collect();
+        remote.fetch(&refspecs, Some(&mut fo), None)?;
+        let stats = remote.stats();
+
+        if stats.local_objects() > 0 {
+            println!(
+                "\rReceived {}/{} objects in {} bytes (used {} local objects)",
+                stats.indexed_objects(),
+                stats.total_objects(),
+                stats.received_bytes(),
+                stats.local_objects()
+            );
         } else {
-            let mut opts = git2::FetchOptions::new();
-            opts.remote_callbacks(callbacks);
-            opts.download_tags(git2::AutotagOption::None);
+            println!(
+                "\rReceived {}/{} objects in {} bytes",
+                stats.indexed_objects(),
+                stats.total_objects(),
+                stats.received_bytes()
+            );
+        }
+        Ok(())
+    }
 
-            let mut builder = git2::build::RepoBuilder::new();
-            builder.fetch_options(opts);
-            builder.branch(&self.config.branch);
+    // the ssh command of the git command, with `git.known_hosts`, `git.host_key_check` and `git.ssh_key`
+    fn ssh_command(&self, url: &str) -> String {
+        let quote = |s: &str| format!("'{}'", s.replace('\'', "'\\''"));
+        let mut ssh = String::from("ssh");
+        let check = match self.config.host_key_check.unwrap_or(HostKeyCheck::Strict) {
+            HostKeyCheck::Strict => "yes",
+            HostKeyCheck::TrustOnFirstUse => "accept-new",
+            HostKeyCheck::Off => "no",
+        };
+        ssh.push_str(&format!(" -o StrictHostKeyChecking={}", check));
+        if let Some(known_hosts) = &self.config.known_hosts {
+            ssh.push_str(&format!(" -o UserKnownHostsFile={}", quote(known_hosts)));
+        }
+        if let (Some(key), _, _) = self.ssh_key(url) {
+            ssh.push_str(&format!(" -i {}", quote(key)));
+        }
+        ssh
+    }
 
-            let repo = builder.clone(&remote_git_path, local_project_path)?;
+    // `git fetch --depth <depth>`, or `git fetch --unshallow` for a full fetch of a shallow repository
+    fn shallow_fetch(
+        &self,
+        repo: &git2::Repository,
+        remote_git_path: &str,
+        refspecs: &[String],
+        depth: u32,
+        all: bool,
+    ) -> Result<(), git2::Error> {
+        let dir = repo
+            .workdir()
+            .ok_or_else(|| git2::Error::from_str("cannot fetch into a bare repository"))?;
+        let remote = match repo.find_remote(&self.config.remote) {
+            Ok(_) => self.config.remote.as_str(),
+            Err(_) => remote_git_path,
+        };
+        let mut command = Command::new("git");
+        // pass the https credentials by environment variables instead of the command line,
+        // the empty `credential.helper` (git >= 2.9) drops the helpers configured in the system
+        let cred = self.user_pass(remote_git_path);
+        if let (Some(cred), true) = (cred, remote_git_path.starts_with("http")) {
+            command
+                .args(["-c", "credential.helper="])
+                .arg("-c")
+                .arg(
+                    "credential.helper=!f() { echo \"username=$AUTO_DEPLOY_GIT_USERNAME\"; \
+                     echo \"password=$AUTO_DEPLOY_GIT_PASSWORD\"; }; f",
+                )
+                .env("AUTO_DEPLOY_GIT_USERNAME", &cred.username)
+                .env("AUTO_DEPLOY_GIT_PASSWORD", &cred.password);
+        }
+        command.arg("fetch").arg("--no-tags");
+        // check the host key with the same known_hosts, unless ssh is configured by the user
+        if ssh_port(remote_git_path).is_some() && env::var_os("GIT_SSH_COMMAND").is_none() {
+            command.env("GIT_SSH_COMMAND", self.ssh_command(remote_git_path));
+        }
+        if !all {
+            command.arg(format!("--depth={}", depth));
+        } else if repo.is_shallow() {
+            command.arg("--unshallow");
+        }
+        command.arg(remote).args(refspecs).current_dir(dir);
+        let status = command
+            .status()
+            .map_err(|e| git2::Error::from_str(&format!("cannot execute git: {}", e)))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(git2::Error::from_str(&format!(
+                "git fetch {} failed: {}",
+                refspecs.join(" "),
+                status
+            )))
         }
+    }
+
+    fn checkout(&self, repo: &git2::Repository) -> Result<(), git2::Error> {
+        match &self.config.reference {
+            Some(reference) => self.checkout_ref(repo, reference),
+            None => self.reset_to_remote(repo),
+        }
+    }
 
+    // force the local branch and the working tree to the fetched `<remote>/<branch>`,
+    // local changes are overwritten and untracked files are removed
+    fn reset_to_remote(&self, repo: &git2::Repository) -> Result<(), git2::Error> {
+        let old = repo.head().ok().and_then(|head| head.target());
+        let remote_branch = format!("refs/remotes/{}/{}", self.config.remote, self.config.branch);
+        let new = repo.refname_to_id(&remote_branch).map_err(|_| {
+            git2::Error::from_str(&format!(
+                "branch {} not found on remote {}",
+                self.config.branch, self.config.remote
+            ))
+        })?;
+        let local_branch = format!("refs/heads/{}", self.config.branch);
+        repo.reference(&local_branch, new, true, "auto-deploy: reset to remote")?;
+        repo.set_head(&local_branch)?;
+        force_checkout(repo, &repo.find_object(new, None)?)?;
+        report(&self.config.branch, old, new);
         Ok(())
     }
+
+    // check out a remote branch, a tag or a commit id detached
+    fn checkout_ref(&self, repo: &git2::Repository, reference: &str) -> Result<(), git2::Error> {
+        let old = repo.head().ok().and_then(|head| head.target());
+        let commit = self.resolve(repo, reference)?;
+        repo.set_head_detached(commit.id())?;
+        force_checkout(repo, commit.as_object())?;
+        report(reference, old, commit.id());
+        Ok(())
+    }
+
+    // the commit of a remote branch, a tag or a commit id
+    fn resolve<'r>(
+        &self,
+        repo: &'r git2::Repository,
+        reference: &str,
+    ) -> Result<git2::Commit<'r>, git2::Error> {
+        let remote_branch = format!("refs/remotes/{}/{}", self.config.remote, reference);
+        let tag = format!("refs/tags/{}", reference);
+        repo.revparse_single(&remote_branch)
+            .or_else(|_| repo.revparse_single(&tag))
+            .or_else(|_| repo.revparse_single(reference))
+            .and_then(|object| object.peel_to_commit())
+            .map_err(|e| {
+                git2::Error::from_str(&format!(
+                    "cannot find branch, tag or commit {}: {}",
+                    reference,
+                    e.message()
+                ))
+            })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum AuthMethod {
+    Http,
+    // ssh asks for the username first when it is not in the url
+    Username,
+    Ssh,
+}
+
+// libssh2 also allows USER_PASS_PLAINTEXT when a ssh server offers password authentication,
+// so the credentials of ssh remotes are always ssh keys
+fn auth_method(url: &str, allowed: git2::CredentialType) -> AuthMethod {
+    let ssh = allowed.contains(git2::CredentialType::SSH_KEY) || ssh_port(url).is_some();
+    let http = url.starts_with("http://") || url.starts_with("https://");
+    if !ssh && http && allowed.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+        AuthMethod::Http
+    } else if allowed.contains(git2::CredentialType::USERNAME) {
+        AuthMethod::Username
+    } else {
+        AuthMethod::Ssh
+    }
+}
+
+// the username of a token without `git.username`, GitHub, GitLab and Gitea accept any username
+static TOKEN_USERNAME: &str = "x-access-token";
+
+// https username and password (or token)
+#[derive(Clone)]
+struct UserPass {
+    username: String,
+    password: String,
+}
+
+impl UserPass {
+    // a token can be used as the password, with any username
+    fn new(
+        username: Option<&String>,
+        token: Option<&config::Secret>,
+        password: Option<&config::Secret>,
+    ) -> Option<Self> {
+        token.or(password).map(|password| UserPass {
+            username: username
+                .cloned()
+                .unwrap_or_else(|| String::from(TOKEN_USERNAME)),
+            password: String::from(password.expose()),
+        })
+    }
+}
+
+// https credentials in the order they are tried: `git.username` and `git.token`/`git.password`,
+// then `git credential fill`, an error is returned when both of them are rejected
+struct HttpAuth {
+    cred: Option<UserPass>,
+    tried_config: bool,
+    tried_helper: bool,
+}
+
+impl HttpAuth {
+    fn new(cred: Option<UserPass>) -> Self {
+        HttpAuth {
+            cred,
+            tried_config: false,
+            tried_helper: false,
+        }
+    }
+
+    fn next(&mut self, url: &str, username: Option<&str>) -> Result<git2::Cred, git2::Error> {
+        if let (Some(cred), false) = (&self.cred, self.tried_config) {
+            self.tried_config = true;
+            println!("authenticate with user {}", cred.username);
+            return git2::Cred::userpass_plaintext(&cred.username, &cred.password);
+        }
+        if !self.tried_helper {
+            self.tried_helper = true;
+            if let Some(cred) = credential_fill(url, username) {
+                println!(
+                    "authenticate with user {} from git credential",
+                    cred.username
+                );
+                return git2::Cred::userpass_plaintext(&cred.username, &cred.password);
+            }
+        }
+        Err(git2::Error::from_str(&format!(
+            "authentication failed for {}, set git.username and git.token (or git.password), \
+             or configure a git credential helper",
+            url
+        )))
+    }
+}
+
+// ask the credential helpers of the system git, without prompting in the terminal
+fn credential_fill(url: &str, username: Option<&str>) -> Option<UserPass> {
+    let mut input = format!("url={}\n", url);
+    if let Some(username) = username {
+        input.push_str(&format!("username={}\n", username));
+    }
+    input.push('\n');
+    let mut child = Command::new("git")
+        .args(["credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?.write_all(input.as_bytes()).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_credential(&String::from_utf8_lossy(&output.stdout))
+}
+
+// `key=value` lines of `git credential fill`
+fn parse_credential(output: &str) -> Option<UserPass> {
+    let value = |key: &str| {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .map(String::from)
+    };
+    Some(UserPass {
+        username: value("username")?,
+        password: value("password")?,
+    })
+}
+
+// ssh credentials in the order they are tried: ssh-agent, `git.ssh_key`, `AUTO_DEPLOY_SSH_KEY`,
+// then `~/.ssh/id_ed25519`, `~/.ssh/id_ecdsa` and `~/.ssh/id_rsa`.
+//
+// libgit2 asks for credentials again after each rejected one, so every candidate is tried once
+// and an error is returned when all of them are rejected instead of looping forever.
+struct SshAuth {
+    candidates: Vec<SshCandidate>,
+    next: usize,
+    passphrase: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum SshCandidate {
+    Agent,
+    Key(path::PathBuf),
+}
+
+static DEFAULT_SSH_KEYS: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+
+impl SshAuth {
+    fn new(
+        ssh_key: Option<&str>,
+        passphrase: Option<&config::Secret>,
+        use_agent: Option<bool>,
+        home: Option<path::PathBuf>,
+        agent: bool,
+    ) -> Self {
+        let mut candidates = Vec::new();
+        if agent && use_agent.unwrap_or(true) {
+            candidates.push(SshCandidate::Agent);
+        }
+        let expand = |key: &str| match (key.strip_prefix("~/"), &home) {
+            (Some(key), Some(home)) => home.join(key),
+            _ => path::PathBuf::from(key),
+        };
+        if let Some(key) = ssh_key {
+            candidates.push(SshCandidate::Key(expand(key)));
+        }
+        if let Ok(key) = env::var("AUTO_DEPLOY_SSH_KEY") {
+            candidates.push(SshCandidate::Key(expand(&key)));
+        }
+        if let Some(home) = &home {
+            for key in DEFAULT_SSH_KEYS {
+                let key = home.join(".ssh").join(key);
+                if key.is_file() && !candidates.contains(&SshCandidate::Key(key.clone())) {
+                    candidates.push(SshCandidate::Key(key));
+                }
+            }
+        }
+        SshAuth {
+            candidates,
+            next: 0,
+            passphrase: passphrase.map(|p| String::from(p.expose())),
+        }
+    }
+
+    fn next(&mut self, user: &str) -> Result<git2::Cred, git2::Error> {
+        let candidate = self.candidates.get(self.next).ok_or_else(|| {
+            git2::Error::from_str(&format!(
+                "ssh authentication failed for user {}, tried: {}",
+                user,
+                self.tried()
+            ))
+        })?;
+        self.next += 1;
+        match candidate {
+            SshCandidate::Agent => {
+                println!("authenticate with user {} and ssh-agent", user);
+                git2::Cred::ssh_key_from_agent(user)
+            }
+            SshCandidate::Key(key) => {
+                println!(
+                    "authenticate with user {} and private key located in {}",
+                    user,
+                    key.display()
+                );
+                git2::Cred::ssh_key(user, None, key, self.passphrase.as_deref())
+            }
+        }
+    }
+
+    fn tried(&self) -> String {
+        if self.candidates.is_empty() {
+            return String::from("no ssh-agent or private key");
+        }
+        self.candidates
+            .iter()
+            .map(|candidate| match candidate {
+                SshCandidate::Agent => String::from("ssh-agent"),
+                SshCandidate::Key(key) => key.display().to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// the url of the repository of a project under `git.prefix`, all git url forms are supported:
+///
+/// * `https://github.com/zidoshare/` + `demo` -> `https://github.com/zidoshare/demo.git`
+/// * `ssh://git@gitea.local:2222/zido` + `demo.git` -> `ssh://git@gitea.local:2222/zido/demo.git`
+/// * `git@github.com:zidoshare` + `parent/child` -> `git@github.com:zidoshare/parent.git`
+/// * `/srv/git` + `demo` -> `/srv/git/demo.git`
+///
+/// a multi-module project `parent/child` is in the repository `parent`.
+pub fn remote_url(prefix: &str, project: &str) -> Result<String, git2::Error> {
+    let repository = project
+        .trim_matches(&['/', '\\'][..])
+        .split(&['/', '\\'][..])
+        .next()
+        .unwrap_or_default();
+    let repository = repository.strip_suffix(".git").unwrap_or(repository);
+    if repository.is_empty() {
+        return Err(git2::Error::from_str(&format!(
+            "invalid project name {}",
+            project
+        )));
+    }
+    let name = format!("{}.git", repository);
+    let invalid = |e: url::ParseError| {
+        git2::Error::from_str(&format!("invalid git.prefix {}: {}", prefix, e))
+    };
+    if prefix.contains("://") {
+        let mut url = url::Url::parse(prefix).map_err(invalid)?;
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+        // `./` keeps a name with `:` relative
+        return url
+            .join(&format!("./{}", name))
+            .map(String::from)
+            .map_err(invalid);
+    }
+    match scp_url(prefix) {
+        Some((authority, path)) => {
+            // the host of a scp-like url is checked as a ssh url
+            if parse_remote(prefix).is_none() {
+                return Err(git2::Error::from_str(&format!(
+                    "invalid git.prefix {}",
+                    prefix
+                )));
+            }
+            let path = path.trim_end_matches('/');
+            if path.is_empty() {
+                Ok(format!("{}:{}", authority, name))
+            } else {
+                Ok(format!("{}:{}/{}", authority, path, name))
+            }
+        }
+        None => Ok(path::Path::new(prefix)
+            .join(name)
+            .to_string_lossy()
+            .into_owned()),
+    }
+}
+
+// `[user@]host:path`, a windows drive letter such as `C:` is a local path
+fn scp_url(url: &str) -> Option<(&str, &str)> {
+    if url.contains("://") {
+        return None;
+    }
+    let (authority, path) = url.split_once(':')?;
+    if authority.len() <= 1 || authority.contains(&['/', '\\'][..]) {
+        return None;
+    }
+    Some((authority, path))
+}
+
+// a remote url parsed by the url crate, a scp-like url is parsed as `ssh://[user@]host/path`
+fn parse_remote(url: &str) -> Option<url::Url> {
+    match scp_url(url) {
+        Some((authority, path)) => url::Url::parse(&format!(
+            "ssh://{}/{}",
+            authority,
+            path.trim_start_matches('/')
+        ))
+        .ok(),
+        None if url.contains("://") => url::Url::parse(url).ok(),
+        None => None,
+    }
+}
+
+// the host of a remote url: `scheme://[user@]host[:port]/path` or `[user@]host:path`
+fn url_host(url: &str) -> Option<String> {
+    parse_remote(url)?.host_str().map(String::from)
+}
+
+// `prefix` is the url itself or one of its parent paths
+fn is_url_prefix(prefix: &str, url: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    match url.strip_prefix(prefix) {
+        Some(rest) => {
+            prefix.contains(['/', ':']) && (rest.is_empty() || rest.starts_with(['/', ':']))
+        }
+        None => false,
+    }
+}
+
+// the port of a ssh url: `ssh://[user@]host[:port]/path` or `[user@]host:path`
+fn ssh_port(url: &str) -> Option<u16> {
+    let url = parse_remote(url)?;
+    match url.scheme() {
+        "ssh" | "git+ssh" | "ssh+git" => Some(url.port().unwrap_or(22)),
+        _ => None,
+    }
+}
+
+// reset the index and the working tree to `target`, untracked files are removed
+fn force_checkout(repo: &git2::Repository, target: &git2::Object) -> Result<(), git2::Error> {
+    repo.reset(target, git2::ResetType::Hard, None)?;
+    // reset does not remove untracked files
+    let mut checkout = git2::build::CheckoutBuilder::new();
+    checkout.force().remove_untracked(true);
+    repo.checkout_head(Some(&mut checkout))
+}
+
+fn report(name: &str, old: Option<git2::Oid>, new: git2::Oid) {
+    match old {
+        Some(old) if old == new => println!("{} is up to date at {}", name, new),
+        Some(old) => println!("reset {} from {} to {}", name, old, new),
+        None => println!("reset {} to {}", name, new),
+    }
+}
+
+/// the commit id of HEAD in a local project
+pub fn head_commit_id(local_project_path: &path::Path) -> Result<String, git2::Error> {
+    let repo = git2::Repository::open(local_project_path)?;
+    let commit = repo.head()?.peel_to_commit()?;
+    Ok(commit.id().to_string())
+}
+
+/// the project of a local git repository, inferred from its remote url and HEAD
+#[derive(Debug, PartialEq)]
+pub struct LocalProject {
+    // 项目名，在子模块中时包含模块相对于仓库根目录的路径，如 parent/child
+    pub project: String,
+    // HEAD 所在的分支，分离头指针时为 None
+    pub branch: Option<String>,
+    // 远程仓库不在 git.prefix 下时为其url
+    pub url: Option<String>,
+}
+
+/// infer the project from the git repository containing `dir`:
+/// the project name is the last path of the url of `remote` without `.git`,
+/// the sub-module path is the nearest directory containing a `pom.xml` relative to the repository root.
+///
+/// returns `None` when `dir` is not in a git repository or the remote does not exist.
+pub fn local_project(dir: &path::Path, remote: &str, prefix: &str) -> Option<LocalProject> {
+    let repo = git2::Repository::discover(dir).ok()?;
+    let url = String::from(repo.find_remote(remote).ok()?.url()?);
+    let trim = |url: &str| {
+        let url = url.trim_end_matches('/');
+        String::from(url.strip_suffix(".git").unwrap_or(url))
+    };
+    let name = trim(&url);
+    let name = name
+        .rsplit(['/', ':'])
+        .next()
+        .filter(|name| !name.is_empty())?;
+    // 不在 git.prefix 下的远程仓库需要使用其url拉取
+    let url = match remote_url(prefix, name) {
+        Ok(under_prefix) if trim(&under_prefix) == trim(&url) => None,
+        _ => Some(url),
+    };
+    let mut project = String::from(name);
+    let root = repo.workdir()?.canonicalize().ok()?;
+    let dir = dir.canonicalize().ok()?;
+    let module = dir
+        .ancestors()
+        .take_while(|dir| dir.starts_with(&root))
+        .find(|dir| dir.join("pom.xml").is_file())
+        .and_then(|module| module.strip_prefix(&root).ok());
+    for component in module.into_iter().flat_map(path::Path::components) {
+        project.push('/');
+        project.push_str(&component.as_os_str().to_string_lossy());
+    }
+    let branch = repo
+        .head()
+        .ok()
+        .filter(|head| head.is_branch())
+        .and_then(|head| head.shorthand().map(String::from));
+    Some(LocalProject {
+        project,
+        branch,
+        url,
+    })
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use crate::config;
-    use crate::git::Git;
+    use crate::git::{
+        auth_method, head_commit_id, is_url_prefix, local_project, parse_credential, remote_url,
+        ssh_port, url_host, AuthMethod, Git, HttpAuth, LocalProject, SshAuth, SshCandidate,
+        TOKEN_USERNAME,
+    };
+    use crate::testing;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // a local repository with one commit on `branch` and the `origin` remote
+    pub fn repository(name: &str, url: &str, branch: &str) -> PathBuf {
+        let path = testing::temp_dir("git").join(name);
+        let repo = git2::Repository::init(&path).unwrap();
+        repo.remote("origin", url).unwrap();
+        fs::create_dir_all(path.join("child/src")).unwrap();
+        let signature = git2::Signature::now("zido", "wuhongxu1208@gmail.com").unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let reference = format!("refs/heads/{}", branch);
+        repo.commit(Some(&reference), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+        repo.set_head(&reference).unwrap();
+        path
+    }
+
+    fn props(prefix: &Path) -> config::GitProps {
+        config::GitProps {
+            remote: String::from("origin"),
+            branch: String::from("master"),
+            prefix: prefix.to_string_lossy().into_owned(),
+            name: None,
+            email: None,
+            username: None,
+            password: None,
+            token: None,
+            reference: None,
+            depth: None,
+            single_branch: None,
+            ssh_key: None,
+            ssh_passphrase: None,
+            use_agent: None,
+            known_hosts: None,
+            host_key_check: None,
+            url: None,
+            credentials: Default::default(),
+        }
+    }
+
+    // commit a file to HEAD of the repository, returns the commit id
+    pub fn commit(path: &Path, file: &str, content: &str) -> String {
+        let repo = git2::Repository::open(path).unwrap();
+        fs::write(path.join(file), content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(file)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("zido", "wuhongxu1208@gmail.com").unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            content,
+            &tree,
+            &[&parent],
+        )
+        .unwrap()
+        .to_string()
+    }
+
+    #[test]
+    fn pull_existing_project_resets_to_remote_branch() {
+        let upstream = repository(
+            "reset-upstream/demo.git",
+            "https://github.com/zidoshare/demo.git",
+            "master",
+        );
+        let prefix = upstream.parent().unwrap().to_path_buf();
+        commit(&upstream, "a.txt", "1");
+        let local = testing::temp_dir("git-reset").join("local");
+        let config = props(&prefix);
+        Git::new(&config).pull_projects("demo", &local).unwrap();
+        assert_eq!("1", fs::read_to_string(local.join("a.txt")).unwrap());
+
+        let head = commit(&upstream, "a.txt", "2");
+        fs::write(local.join("a.txt"), "local change").unwrap();
+        fs::write(local.join("untracked.txt"), "untracked").unwrap();
+        Git::new(&config).pull_projects("demo", &local).unwrap();
+        assert_eq!("2", fs::read_to_string(local.join("a.txt")).unwrap());
+        assert!(!local.join("untracked.txt").exists());
+        assert_eq!(head, head_commit_id(&local).unwrap());
+
+        let mut config = props(&prefix);
+        config.branch = String::from("mastre");
+        let err = Git::new(&config).pull_projects("demo", &local).unwrap_err();
+        assert_eq!("branch mastre not found on remote origin", err.message());
+    }
+
+    #[test]
+    fn pull_project_from_its_own_url() {
+        let upstream = repository(
+            "url-upstream/other-name.git",
+            "https://github.com/zidoshare/demo.git",
+            "master",
+        );
+        commit(&upstream, "a.txt", "1");
+        let local = testing::temp_dir("git-url").join("local");
+        let mut config = props(Path::new("/not/exists"));
+        config.url = Some(upstream.to_string_lossy().into_owned());
+        Git::new(&config).pull_projects("demo", &local).unwrap();
+        assert_eq!("1", fs::read_to_string(local.join("a.txt")).unwrap());
+
+        // the project moved to another url
+        let moved = repository(
+            "url-upstream/moved.git",
+            "https://github.com/zidoshare/demo.git",
+            "master",
+        );
+        commit(&moved, "a.txt", "moved");
+        config.url = Some(moved.to_string_lossy().into_owned());
+        Git::new(&config).pull_projects("demo", &local).unwrap();
+        assert_eq!("moved", fs::read_to_string(local.join("a.txt")).unwrap());
+        let repo = git2::Repository::open(&local).unwrap();
+        assert_eq!(
+            config.url.as_deref(),
+            repo.find_remote("origin").unwrap().url()
+        );
+    }
+
+    #[test]
+    fn pull_project_at_tag_commit_or_branch() {
+        let upstream = repository(
+            "ref-upstream/demo.git",
+            "https://github.com/zidoshare/demo.git",
+            "master",
+        );
+        let prefix = upstream.parent().unwrap().to_path_buf();
+        let first = commit(&upstream, "a.txt", "1");
+        let repo = git2::Repository::open(&upstream).unwrap();
+        let object = repo.revparse_single(&first).unwrap();
+        repo.tag_lightweight("v1", &object, false).unwrap();
+        let second = commit(&upstream, "a.txt", "2");
+        let local = testing::temp_dir("git-ref").join("local");
+        let mut config = props(&prefix);
+
+        config.reference = Some(String::from("v1"));
+        Git::new(&config).pull_projects("demo", &local).unwrap();
+        assert_eq!("1", fs::read_to_string(local.join("a.txt")).unwrap());
+        assert_eq!(first, head_commit_id(&local).unwrap());
+        assert!(git2::Repository::open(&local)
+            .unwrap()
+            .head_detached()
+            .unwrap());
+
+        config.reference = Some(second.clone());
+        Git::new(&config).pull_projects("demo", &local).unwrap();
+        assert_eq!("2", fs::read_to_string(local.join("a.txt")).unwrap());
+        assert_eq!(second, head_commit_id(&local).unwrap());
+
+        config.reference = Some(first[..7].to_owned());
+        Git::new(&config).pull_projects("demo", &local).unwrap();
+        assert_eq!(first, head_commit_id(&local).unwrap());
+
+        config.reference = Some(String::from("master"));
+        Git::new(&config).pull_projects("demo", &local).unwrap();
+        assert_eq!(second, head_commit_id(&local).unwrap());
+
+        config.reference = Some(String::from("not-exists"));
+        let err = Git::new(&config).pull_projects("demo", &local).unwrap_err();
+        assert!(err.message().contains("not-exists"));
+    }
+
+    #[test]
+    fn shallow_clone_and_fallback_to_full_fetch() {
+        let upstream = repository(
+            "shallow-upstream/demo.git",
+            "https://github.com/zidoshare/demo.git",
+            "master",
+        );
+        let prefix = upstream.parent().unwrap().to_path_buf();
+        let first = commit(&upstream, "a.txt", "1");
+        let second = commit(&upstream, "a.txt", "2");
+        let local = testing::temp_dir("git-shallow").join("local");
+        let mut config = props(&prefix);
+        config.depth = Some(1);
+
+        Git::new(&config).pull_projects("demo", &local).unwrap();
+        assert_eq!("2", fs::read_to_string(local.join("a.txt")).unwrap());
+        assert_eq!(second, head_commit_id(&local).unwrap());
+        assert!(git2::Repository::open(&local).unwrap().is_shallow());
+
+        // a short commit id can not be fetched directly
+        config.reference = Some(first[..7].to_owned());
+        Git::new(&config).pull_projects("demo", &local).unwrap();
+        assert_eq!("1", fs::read_to_string(local.join("a.txt")).unwrap());
+        assert_eq!(first, head_commit_id(&local).unwrap());
+        assert!(!git2::Repository::open(&local).unwrap().is_shallow());
+    }
+
+    #[test]
+    fn single_branch_clone_fetches_only_the_ref() {
+        let upstream = repository(
+            "single-upstream/demo.git",
+            "https://github.com/zidoshare/demo.git",
+            "master",
+        );
+        let prefix = upstream.parent().unwrap().to_path_buf();
+        let first = commit(&upstream, "a.txt", "1");
+        let repo = git2::Repository::open(&upstream).unwrap();
+        let object = repo.revparse_single(&first).unwrap();
+        repo.tag_lightweight("v1", &object, false).unwrap();
+        repo.branch("other", &object.peel_to_commit().unwrap(), false)
+            .unwrap();
+        commit(&upstream, "a.txt", "2");
+        let local = testing::temp_dir("git-single").join("local");
+        let mut config = props(&prefix);
+        config.single_branch = Some(true);
+        config.reference = Some(String::from("v1"));
+
+        Git::new(&config).pull_projects("demo", &local).unwrap();
+        assert_eq!(first, head_commit_id(&local).unwrap());
+        let repo = git2::Repository::open(&local).unwrap();
+        assert!(repo.find_reference("refs/tags/v1").is_ok());
+        assert!(repo.find_reference("refs/remotes/origin/master").is_err());
+        assert!(repo.find_reference("refs/remotes/origin/other").is_err());
+    }
+
+    #[test]
+    fn ssh_auth_tries_agent_then_keys_then_fails() {
+        let home = testing::temp_dir("git-ssh-home");
+        fs::create_dir_all(home.join(".ssh")).unwrap();
+        for key in &["id_rsa", "id_ed25519", "deploy"] {
+            fs::write(home.join(".ssh").join(key), "").unwrap();
+        }
+        let passphrase = config::Secret::new("secret");
+        let key = Some("~/.ssh/deploy");
+
+        let mut ssh = SshAuth::new(key, Some(&passphrase), None, Some(home.clone()), true);
+        assert_eq!(
+            vec![
+                SshCandidate::Agent,
+                SshCandidate::Key(home.join(".ssh/deploy")),
+                SshCandidate::Key(home.join(".ssh/id_ed25519")),
+                SshCandidate::Key(home.join(".ssh/id_rsa")),
+            ],
+            ssh.candidates
+        );
+        for _ in 0..4 {
+            ssh.next("git").unwrap();
+        }
+        let err = ssh.next("git").err().unwrap();
+        assert!(err.message().contains("tried: ssh-agent"));
+
+        let ssh = SshAuth::new(None, None, Some(false), Some(home.clone()), true);
+        assert_eq!(
+            Some(&SshCandidate::Key(home.join(".ssh/id_ed25519"))),
+            ssh.candidates.first()
+        );
+        let err = SshAuth::new(None, None, None, None, false)
+            .next("git")
+            .err()
+            .unwrap();
+        assert!(err.message().contains("no ssh-agent or private key"));
+    }
+
+    #[test]
+    fn ssh_keys_are_used_when_ssh_server_also_offers_passwords() {
+        use git2::CredentialType;
+        let both = CredentialType::SSH_KEY | CredentialType::USER_PASS_PLAINTEXT;
+        assert_eq!(
+            AuthMethod::Ssh,
+            auth_method("git@gitea.local:zido/demo.git", both)
+        );
+        assert_eq!(
+            AuthMethod::Ssh,
+            auth_method(
+                "ssh://git@gitea.local:2222/zido/demo.git",
+                CredentialType::USER_PASS_PLAINTEXT
+            )
+        );
+        assert_eq!(
+            AuthMethod::Username,
+            auth_method("git@gitea.local:zido/demo.git", CredentialType::USERNAME)
+        );
+        assert_eq!(
+            AuthMethod::Http,
+            auth_method(
+                "https://gitea.local/zido/demo.git",
+                CredentialType::USER_PASS_PLAINTEXT
+            )
+        );
+    }
+
+    #[test]
+    fn token_is_used_as_https_password() {
+        let mut config = props(Path::new("https://github.com/zidoshare"));
+        config.password = Some(config::Secret::new("password"));
+        config.token = Some(config::Secret::new("token"));
+        let git = Git::new(&config);
+        let cred = git.cred.as_ref().unwrap();
+        assert_eq!(TOKEN_USERNAME, cred.username);
+        assert_eq!("token", cred.password);
+
+        config.username = Some(String::from("zido"));
+        config.token = None;
+        let git = Git::new(&config);
+        let cred = git.cred.as_ref().unwrap();
+        assert_eq!("zido", cred.username);
+        assert_eq!("password", cred.password);
+
+        let mut http = HttpAuth::new(Some(cred.clone()));
+        http.next("https://github.com/zidoshare/demo.git", None)
+            .unwrap();
+        assert!(http.tried_config);
+    }
+
+    #[test]
+    fn credentials_by_remote_url() {
+        let mut config = props(Path::new("git@github.com:zidoshare"));
+        config.username = Some(String::from("global"));
+        config.password = Some(config::Secret::new("global"));
+        let credential = |username: &str| config::CredentialProps {
+            username: Some(String::from(username)),
+            token: Some(config::Secret::new(username)),
+            ..Default::default()
+        };
+        config
+            .credentials
+            .insert(String::from("gitea.example.com"), credential("gitea"));
+        config.credentials.insert(
+            String::from("https://gitea.example.com/team/"),
+            credential("team"),
+        );
+        config
+            .credentials
+            .insert(String::from("github.com"), credential("github"));
+        let git = Git::new(&config);
+        let username = |url: &str| git.user_pass(url).unwrap().username;
+
+        assert_eq!("gitea", username("https://gitea.example.com/zido/demo.git"));
+        assert_eq!(
+            "gitea",
+            username("ssh://git@gitea.example.com:2222/zido/demo.git")
+        );
+        assert_eq!("team", username("https://gitea.example.com/team/demo.git"));
+        assert_eq!(
+            "gitea",
+            username("https://gitea.example.com/team-b/demo.git")
+        );
+        assert_eq!("github", username("git@github.com:zidoshare/demo.git"));
+        assert_eq!("global", username("https://gitlab.com/zidoshare/demo.git"));
+        assert_eq!(
+            (None, None, None),
+            git.ssh_key("git@github.com:zidoshare/demo.git")
+        );
+    }
+
+    #[test]
+    fn host_and_prefix_of_urls() {
+        assert_eq!(
+            Some("github.com"),
+            url_host("git@github.com:zidoshare/demo.git").as_deref()
+        );
+        assert_eq!(
+            Some("gitea.local"),
+            url_host("ssh://git@gitea.local:2222/zido/demo.git").as_deref()
+        );
+        assert_eq!(
+            Some("github.com"),
+            url_host("https://token@github.com/zidoshare/demo.git").as_deref()
+        );
+        assert_eq!(None, url_host("/tmp/repositories/demo.git"));
+        assert!(is_url_prefix(
+            "git@github.com:zidoshare",
+            "git@github.com:zidoshare/demo.git"
+        ));
+        assert!(is_url_prefix(
+            "https://github.com/",
+            "https://github.com/zidoshare/demo.git"
+        ));
+        assert!(!is_url_prefix(
+            "https://github.com/zido",
+            "https://github.com/zidoshare/demo.git"
+        ));
+        assert!(!is_url_prefix(
+            "github.com",
+            "github.com:zidoshare/demo.git"
+        ));
+    }
+
+    #[test]
+    fn parse_credential_fill_output() {
+        let cred =
+            parse_credential("protocol=https\nhost=github.com\nusername=zido\npassword=p=w\n")
+                .unwrap();
+        assert_eq!("zido", cred.username);
+        assert_eq!("p=w", cred.password);
+        assert!(parse_credential("protocol=https\nhost=github.com\n").is_none());
+    }
+
+    #[test]
+    fn remote_url_of_all_prefix_forms() {
+        let url = |prefix: &str, project: &str| remote_url(prefix, project).unwrap();
+        assert_eq!(
+            "git@github.com:zidoshare/demo.git",
+            url("git@github.com:zidoshare", "demo")
+        );
+        assert_eq!(
+            "git@github.com:zidoshare/demo.git",
+            url("git@github.com:zidoshare/", "demo.git")
+        );
+        assert_eq!("git@github.com:demo.git", url("git@github.com:", "demo"));
+        assert_eq!(
+            "git@example.com:/srv/git/parent.git",
+            url("git@example.com:/srv/git", "parent/child")
+        );
+        assert_eq!(
+            "https://github.com/zidoshare/demo.git",
+            url("https://github.com/zidoshare/", "demo")
+        );
+        assert_eq!(
+            "https://github.com/zidoshare/parent.git",
+            url("https://github.com/zidoshare", "/parent/child/")
+        );
+        assert_eq!(
+            "ssh://git@gitea.local:2222/zido/demo.git",
+            url("ssh://git@gitea.local:2222/zido", "demo")
+        );
+        assert_eq!(
+            "ssh://git@gitea.local:2222/demo.git",
+            url("ssh://git@gitea.local:2222", "demo")
+        );
+        assert_eq!("/srv/git/demo.git", url("/srv/git/", "demo"));
+        assert!(remote_url("https://", "demo").is_err());
+        assert!(remote_url("git@github.com:zidoshare", "/").is_err());
+    }
+
+    #[test]
+    fn ssh_port_of_urls() {
+        assert_eq!(Some(22), ssh_port("git@github.com:zidoshare/demo.git"));
+        assert_eq!(
+            Some(22),
+            ssh_port("ssh://git@github.com/zidoshare/demo.git")
+        );
+        assert_eq!(
+            Some(2222),
+            ssh_port("ssh://git@gitea.local:2222/zido/demo.git")
+        );
+        assert_eq!(None, ssh_port("https://github.com/zidoshare/demo.git"));
+        assert_eq!(None, ssh_port("/tmp/repositories/demo.git"));
+    }
+
+    #[test]
+    fn local_project_from_remote_url_and_head() {
+        let path = repository("local", "git@github.com:zidoshare/demo.git", "online");
+        assert_eq!(
+            Some(LocalProject {
+                project: String::from("demo"),
+                branch: Some(String::from("online")),
+                url: None,
+            }),
+            local_project(
+                &path.join("child/src"),
+                "origin",
+                "git@github.com:zidoshare/"
+            )
+        );
+        fs::write(path.join("child/pom.xml"), "<project/>").unwrap();
+        let project = local_project(&path.join("child/src"), "origin", "git@github.com:other/");
+        assert_eq!(
+            Some(LocalProject {
+                project: String::from("demo/child"),
+                branch: Some(String::from("online")),
+                url: Some(String::from("git@github.com:zidoshare/demo.git")),
+            }),
+            project
+        );
+        assert_eq!(
+            None,
+            local_project(&path, "upstream", "git@github.com:zidoshare")
+        );
+        assert_eq!(None, local_project(Path::new("/"), "origin", ""));
+    }
 
     #[test]
     #[ignore]
@@ -171,6 +1432,17 @@ mod test {
             email: Some(String::from("wuhongxu1208@gmail.com")),
             username: None,
             password: None,
+            token: None,
+            reference: None,
+            depth: None,
+            single_branch: None,
+            ssh_key: None,
+            ssh_passphrase: None,
+            use_agent: None,
+            known_hosts: None,
+            host_key_check: None,
+            url: None,
+            credentials: Default::default(),
         })
         .pull_projects("zicode-script.js", std::path::Path::new("./test"))
         .unwrap();
@@ -189,6 +1461,17 @@ mod test {
             email: Some(String::from("wuhongxu1208@gmail.com")),
             username: None,
             password: None,
+            token: None,
+            reference: None,
+            depth: None,
+            single_branch: None,
+            ssh_key: None,
+            ssh_passphrase: None,
+            use_agent: None,
+            known_hosts: None,
+            host_key_check: None,
+            url: None,
+            credentials: Default::default(),
         })
         .pull_projects("not_exists_project", std::path::Path::new("./test"))
         .unwrap();
@@ -200,7 +1483,7 @@ mod test {
         let test_path = std::path::Path::new("./test");
         //clear source
         if test_path.exists() {
-            std::fs::remove_dir_all(&test_path).unwrap();
+            std::fs::remove_dir_all(test_path).unwrap();
         }
         let config = config::GitProps {
             remote: String::from("origin"),
@@ -210,6 +1493,17 @@ mod test {
             email: Some(String::from("wuhongxu1208@gmail.com")),
             username: None,
             password: None,
+            token: None,
+            reference: None,
+            depth: None,
+            single_branch: None,
+            ssh_key: None,
+            ssh_passphrase: None,
+            use_agent: None,
+            known_hosts: None,
+            host_key_check: None,
+            url: None,
+            credentials: Default::default(),
         };
         //clone projects
         Git::new(&config)
@@ -218,12 +1512,12 @@ mod test {
         assert!(test_path.exists());
         // git pull projects
         Git::new(&config)
-            .pull_projects("zicode-script.js", &test_path)
+            .pull_projects("zicode-script.js", test_path)
             .unwrap();
         assert!(test_path.exists());
         //clear source
         if test_path.exists() {
-            std::fs::remove_dir_all(&test_path).unwrap();
+            std::fs::remove_dir_all(test_path).unwrap();
         }
     }
 }
@@ -0,0 +1,290 @@
+//! ssh host key verification against an OpenSSH `known_hosts` file.
+//!
+//! libgit2 only exposes the SHA1/MD5 hash of the host key, so the keys in `known_hosts`
+//! are compared by their SHA1 hash, and new keys are looked up with `ssh-keyscan`.
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha1::{Digest, Sha1};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Debug, PartialEq)]
+pub enum Check {
+    /// the host key is one of the known keys of the host
+    Match,
+    /// the host is not in `known_hosts`
+    Unknown,
+    /// the host key differs from all of the known keys of the host, with the types of the known keys
+    Mismatch(Vec<String>),
+    /// the host key is marked `@revoked`
+    Revoked,
+}
+
+/// check the SHA1 hash of a host key against `known_hosts`, a missing file has no known hosts
+pub fn check(path: &Path, host: &str, port: u16, sha1: &[u8; 20]) -> io::Result<Check> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let name = host_name(host, port);
+    let (mut matched, mut known) = (false, Vec::new());
+    for entry in contents.lines().filter_map(Entry::parse) {
+        if !matches_host(entry.hosts, &name) {
+            continue;
+        }
+        let same = &sha1_digest(&entry.key) == sha1;
+        match entry.marker {
+            Some("@revoked") if same => return Ok(Check::Revoked),
+            Some(_) => {}
+            None if same => matched = true,
+            None => known.push(String::from(entry.key_type)),
+        }
+    }
+    known.sort();
+    known.dedup();
+    Ok(match (matched, known.is_empty()) {
+        (true, _) => Check::Match,
+        (false, true) => Check::Unknown,
+        (false, false) => Check::Mismatch(known),
+    })
+}
+
+/// trust on first use: find the host key with `ssh-keyscan` and append it to `known_hosts`
+pub fn record(path: &Path, host: &str, port: u16, sha1: &[u8; 20]) -> io::Result<()> {
+    let line = keyscan(host, port, sha1)?;
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // keep the new entry on its own line
+    let contents = fs::read(path)?;
+    if contents.last().is_some_and(|c| *c != b'\n') {
+        writeln!(file)?;
+    }
+    writeln!(file, "{}", line)
+}
+
+/// the type of a host key, such as `ssh-ed25519`, found with `ssh-keyscan`
+pub fn key_type(host: &str, port: u16, sha1: &[u8; 20]) -> io::Result<String> {
+    let line = keyscan(host, port, sha1)?;
+    let entry = Entry::parse(&line).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("invalid key {}", line))
+    })?;
+    Ok(String::from(entry.key_type))
+}
+
+// the `ssh-keyscan` output line of the host key
+fn keyscan(host: &str, port: u16, sha1: &[u8; 20]) -> io::Result<String> {
+    let output = Command::new("ssh-keyscan")
+        .arg("-p")
+        .arg(port.to_string())
+        .arg(host)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot execute ssh-keyscan: {}", e)))?;
+    let keyscan = String::from_utf8_lossy(&output.stdout);
+    let line = find_key(&keyscan, sha1).map(String::from).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "ssh-keyscan did not return the host key {} of {}",
+                fingerprint(sha1),
+                host
+            ),
+        )
+    });
+    line
+}
+
+/// the fingerprint of a host key as printed by `ssh-keygen -l -E sha1`
+pub fn fingerprint(sha1: &[u8; 20]) -> String {
+    format!("SHA1:{}", STANDARD_NO_PAD.encode(sha1))
+}
+
+// the known_hosts name of a host, `[host]:port` when the port is not 22
+fn host_name(host: &str, port: u16) -> String {
+    let host = host.to_lowercase();
+    if port == 22 {
+        host
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+// `[@marker] <hosts> <key type> <base64 key> [comment]`
+struct Entry<'a> {
+    marker: Option<&'a str>,
+    hosts: &'a str,
+    key_type: &'a str,
+    key: Vec<u8>,
+}
+
+impl<'a> Entry<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let mut hosts = fields.next().filter(|f| !f.starts_with('#'))?;
+        let mut marker = None;
+        if hosts.starts_with('@') {
+            marker = Some(hosts);
+            hosts = fields.next()?;
+        }
+        let key_type = fields.next()?;
+        let key = STANDARD.decode(fields.next()?).ok()?;
+        Some(Entry {
+            marker,
+            hosts,
+            key_type,
+            key,
+        })
+    }
+}
+
+// comma separated patterns with `*`/`?` wildcards, `!` negation and `|1|salt|hash` hashed names
+fn matches_host(patterns: &str, name: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.split(',') {
+        if let Some(hashed) = pattern.strip_prefix("|1|") {
+            let mut parts = hashed.splitn(2, '|');
+            let salt = parts.next().and_then(|salt| STANDARD.decode(salt).ok());
+            let hash = parts.next().and_then(|hash| STANDARD.decode(hash).ok());
+            if let (Some(salt), Some(hash)) = (salt, hash) {
+                matched |= Hmac::<Sha1>::new_from_slice(&salt)
+                    .map(|mac| {
+                        mac.chain_update(name.as_bytes())
+                            .verify_slice(&hash)
+                            .is_ok()
+                    })
+                    .unwrap_or(false);
+            }
+            continue;
+        }
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        if wildcard(pattern.to_lowercase().as_bytes(), name.as_bytes()) {
+            if negated {
+                return false;
+            }
+            matched = true;
+        }
+    }
+    matched
+}
+
+fn wildcard(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard(&pattern[1..], name) || (!name.is_empty() && wildcard(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => wildcard(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+// the `ssh-keyscan` output line of the key with the SHA1 hash
+fn find_key<'a>(keyscan: &'a str, sha1: &[u8; 20]) -> Option<&'a str> {
+    keyscan
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .find(|line| Entry::parse(line).is_some_and(|entry| &sha1_digest(&entry.key) == sha1))
+}
+
+fn sha1_digest(data: &[u8]) -> [u8; 20] {
+    Sha1::digest(data).into()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::git::known_hosts::*;
+    use crate::testing;
+
+    fn known_hosts(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = testing::temp_dir(&format!("known-hosts-{}", name)).join("known_hosts");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn check_plain_hashed_and_revoked_entries() {
+        let (key, other) = (b"host key".to_vec(), b"other key".to_vec());
+        let sha1 = sha1_digest(&key);
+        let salt = b"01234567890123456789";
+        let hashed = format!(
+            "|1|{}|{}",
+            STANDARD.encode(salt),
+            STANDARD.encode(
+                Hmac::<Sha1>::new_from_slice(salt)
+                    .unwrap()
+                    .chain_update(b"[git.example.com]:2222")
+                    .finalize()
+                    .into_bytes()
+            )
+        );
+        let path = known_hosts(
+            "check",
+            &format!(
+                "# comment\n\
+                 github.com,140.82.112.3 ssh-ed25519 {key}\n\
+                 *.example.com,!evil.example.com ssh-rsa {other}\n\
+                 {hashed} ssh-ed25519 {key}\n\
+                 @revoked revoked.com ssh-ed25519 {key}\n\
+                 revoked.com ssh-ed25519 {key}\n",
+                key = STANDARD.encode(&key),
+                other = STANDARD.encode(&other),
+                hashed = hashed,
+            ),
+        );
+        assert_eq!(Check::Match, check(&path, "GitHub.com", 22, &sha1).unwrap());
+        assert_eq!(
+            Check::Unknown,
+            check(&path, "github.com", 2222, &sha1).unwrap()
+        );
+        assert_eq!(
+            Check::Mismatch(vec![String::from("ssh-rsa")]),
+            check(&path, "gitea.example.com", 22, &sha1).unwrap()
+        );
+        assert_eq!(
+            Check::Unknown,
+            check(&path, "evil.example.com", 22, &sha1).unwrap()
+        );
+        assert_eq!(
+            Check::Match,
+            check(&path, "git.example.com", 2222, &sha1).unwrap()
+        );
+        assert_eq!(
+            Check::Revoked,
+            check(&path, "revoked.com", 22, &sha1).unwrap()
+        );
+        let missing = testing::temp_dir("known-hosts-missing").join("known_hosts");
+        assert_eq!(
+            Check::Unknown,
+            check(&missing, "github.com", 22, &sha1).unwrap()
+        );
+    }
+
+    #[test]
+    fn find_key_in_keyscan_output() {
+        let key = STANDARD.encode(b"host key");
+        let output = format!(
+            "# github.com:22 SSH-2.0-babeld\ngithub.com ssh-rsa {}\ngithub.com ssh-ed25519 {}\n",
+            STANDARD.encode(b"rsa key"),
+            key
+        );
+        assert_eq!(
+            Some(format!("github.com ssh-ed25519 {}", key).as_str()),
+            find_key(&output, &sha1_digest(b"host key"))
+        );
+        assert_eq!(None, find_key(&output, &sha1_digest(b"unknown key")));
+        assert_eq!(
+            "SHA1:aGVsbG8gd29ybGQhISEhISEhISE",
+            fingerprint(b"hello world!!!!!!!!!")
+        );
+    }
+}
@@ -0,0 +1,26 @@
+//! java maven 项目服务器构建部署工具，适合小型单机项目，约定优于配置
+//!
+//! 从git拉取 -> 校验并构建项目 -> 备份原版本 -> 发布/回滚新版本，
+//! 入口为 [`Deployer`](deploy/struct.Deployer.html)。
+extern crate clap;
+extern crate git2;
+extern crate java_properties;
+extern crate quick_xml;
+extern crate serde;
+extern crate toml;
+extern crate url;
+extern crate yaml_rust;
+pub mod backup;
+pub mod config;
+pub mod deploy;
+pub mod error;
+pub mod git;
+pub mod maven;
+pub mod projects;
+#[cfg(test)]
+pub(crate) mod testing;
+
+pub use crate::config::DeployConfig;
+pub use crate::deploy::Deployer;
+pub use crate::error::{Error, Result};
+pub use crate::git::Git;
@@ -1,17 +1,100 @@
-extern crate clap;
-extern crate git2;
-extern crate java_properties;
-extern crate quick_xml;
-extern crate serde;
-extern crate toml;
-extern crate url;
-extern crate yaml_rust;
-mod config;
-mod git;
-mod projects;
+use auto_deploy::config::init;
+use auto_deploy::config::validate::{self, Level};
+use auto_deploy::config::{Command, ConfigLoader, LayeredConfig};
+use auto_deploy::{Deployer, Error, Result};
+use std::process;
 static DEFAULT_CONFIG_PATH: &str = "/etc/auto-deploy/config.toml";
 
 fn main() {
-    let config = config::get_config(DEFAULT_CONFIG_PATH);
-    println!("{:#?}", config);
+    let config = match ConfigLoader::new(DEFAULT_CONFIG_PATH).load_layers() {
+        Ok(layered) => {
+            if *layered.command() != Command::ConfigValidate {
+                for warning in layered.warnings() {
+                    eprintln!("warning: {}", warning);
+                }
+            }
+            if let Some(result) = config_command(&layered) {
+                if let Err(e) = result {
+                    eprintln!("{}", e);
+                    process::exit(e.exit_code());
+                }
+                return;
+            }
+            layered.into_config()
+        }
+        Err(e) => Err(e),
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(e.exit_code());
+        }
+    };
+    let deployer = Deployer::new(config);
+    if let Command::Rollback { generation, list } = deployer.config().command {
+        let project = &deployer
+            .config()
+            .projects
+            .as_ref()
+            .expect("need provide project")[0];
+        if let Err(e) = deployer.rollback(project, generation, list) {
+            eprintln!("rollback project {} failed: {}", project, e);
+            process::exit(e.exit_code());
+        }
+        return;
+    }
+    println!("{:#?}", deployer.config());
+    let failed = deployer.deploy_all();
+    if !failed.is_empty() {
+        eprintln!("failed projects:");
+        for (project, e) in &failed {
+            eprintln!("  {}: {}", project, e);
+        }
+        // the exit code of the error when all projects failed by the same kind of error
+        let code = failed[0].1.exit_code();
+        if failed.iter().all(|(_, e)| e.exit_code() == code) {
+            process::exit(code);
+        }
+        process::exit(1);
+    }
+}
+
+// run the `config` subcommands, returns `None` for other commands
+fn config_command(layered: &LayeredConfig) -> Option<Result<()>> {
+    match *layered.command() {
+        Command::ConfigShow { origin } => {
+            print!("{}", layered.show(origin));
+            Some(Ok(()))
+        }
+        Command::ConfigValidate => Some(validate(layered)),
+        Command::ConfigInit { force } => {
+            Some(init::Probe::new().and_then(|probe| init::init(layered.path(), force, &probe)))
+        }
+        Command::Help(ref help) => {
+            println!("{}", help);
+            Some(Ok(()))
+        }
+        _ => None,
+    }
+}
+
+fn validate(layered: &LayeredConfig) -> Result<()> {
+    let diagnostics = validate::validate(layered);
+    for diagnostic in &diagnostics {
+        eprintln!("{}: {}", layered.path(), diagnostic);
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.level == Level::Error)
+        .count();
+    if errors > 0 {
+        return Err(Error::Config(format!(
+            "{} has {} error(s)",
+            layered.path(),
+            errors
+        )));
+    }
+    println!("{}: ok", layered.path());
+    Ok(())
 }
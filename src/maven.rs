@@ -0,0 +1,103 @@
+use crate::config::MavenProps;
+use crate::error::{Error, Result};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+
+/// package project with maven: `mvn package`,run in the project (or sub-module) directory.
+///
+/// the build output is captured and only printed when the build fails.
+/// tests are skipped unless `maven.skip_tests` is `false`.
+pub fn package(config: &MavenProps, project_path: &Path) -> Result<()> {
+    println!("build project {}...", project_path.display());
+    let mut command = Command::new(&config.bin);
+    command
+        .arg("package")
+        .arg("--batch-mode")
+        .arg(format!("-Dmaven.repo.local={}", config.repository))
+        .current_dir(project_path);
+    if config.skip_tests.unwrap_or(true) {
+        command.arg("-Dmaven.test.skip=true");
+    }
+    let output = command
+        .output()
+        .map_err(|e| Error::Build(format!("cannot execute maven {}: {}", config.bin, e)))?;
+    if !output.status.success() {
+        io::stdout().write_all(&output.stdout)?;
+        io::stderr().write_all(&output.stderr)?;
+        return Err(Error::Build(format!(
+            "build project {} failed: {}",
+            project_path.display(),
+            output.status
+        )));
+    }
+    println!("build success");
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use crate::config::MavenProps;
+    use crate::maven::package;
+    use crate::testing;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::sync::OnceLock;
+
+    static FAKE_MAVEN: OnceLock<PathBuf> = OnceLock::new();
+
+    // a fake mvn used as `maven.bin`: records its arguments, fails when `fail` exists in the work dir
+    fn fake_maven() -> &'static PathBuf {
+        FAKE_MAVEN.get_or_init(|| {
+            let mvn = testing::temp_dir("fake-maven").join("mvn");
+            fs::write(
+                &mvn,
+                "#!/bin/sh\n\
+                 echo \"$@\" > mvn-args\n\
+                 if [ -f fail ]; then echo '[ERROR] BUILD FAILURE'; exit 1; fi\n",
+            )
+            .unwrap();
+            fs::set_permissions(&mvn, fs::Permissions::from_mode(0o755)).unwrap();
+            mvn
+        })
+    }
+
+    fn project(name: &str) -> PathBuf {
+        testing::temp_dir(&format!("maven-{}", name))
+    }
+
+    fn props(skip_tests: Option<bool>) -> MavenProps {
+        MavenProps {
+            bin: fake_maven().to_string_lossy().into_owned(),
+            repository: String::from("/tmp/repository"),
+            skip_tests,
+        }
+    }
+
+    #[test]
+    fn package_skip_tests_by_default() {
+        let project = project("skip-tests");
+        package(&props(None), &project).unwrap();
+        let args = fs::read_to_string(project.join("mvn-args")).unwrap();
+        assert!(args.starts_with("package"));
+        assert!(args.contains("-Dmaven.repo.local=/tmp/repository"));
+        assert!(args.contains("-Dmaven.test.skip=true"));
+    }
+
+    #[test]
+    fn package_with_tests_when_skip_tests_is_false() {
+        let project = project("with-tests");
+        package(&props(Some(false)), &project).unwrap();
+        let args = fs::read_to_string(project.join("mvn-args")).unwrap();
+        assert!(!args.contains("-Dmaven.test.skip=true"));
+    }
+
+    #[test]
+    fn package_failed_should_return_err() {
+        let project = project("failed");
+        fs::write(project.join("fail"), "").unwrap();
+        let err = package(&props(None), &project).unwrap_err();
+        assert!(err.to_string().contains("build project"));
+    }
+}
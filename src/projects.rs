@@ -1,26 +1,34 @@
-use java_properties;
+use crate::error::{Error, Result};
+use java_properties::{LineContent, PropertiesIter};
 use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
-use quick_xml::{Error, Reader, Writer};
+use quick_xml::{Reader, Writer};
 use std::fs::{self, File};
-use std::io::{BufReader, BufWriter, Cursor};
+use std::io::Cursor;
+use std::path::Path;
 use std::str;
-use yaml_rust;
+use yaml_rust::YamlLoader;
 static INVALID_END_PATH_VEC: &[char] = &['/', '\\'];
-//// validate project path,eg. application-${env}.properties
+static PROFILES_ACTIVE: &str = "spring.profiles.active";
+static LINE_END_VEC: &[char] = &['\r', '\n'];
+/// validate project path,eg. application-${env}.properties
 ///  and set application.profiles to ${env}.
 ///
 /// # Example:
-/// ```rust
+/// ```no_run
+/// use auto_deploy::projects::validate_project;
+///
 /// let project_path = "/data/some-server";
-/// validate_project(project_path,"test");
+/// validate_project(project_path, "test").unwrap();
 /// ```
 ///
 /// # with submodule starter
-/// ```rust
+/// ```no_run
+/// use auto_deploy::projects::validate_project;
+///
 /// let project_path = "/data/parent-module/sub-module";
-/// validate_project(project_path,"test");
+/// validate_project(project_path, "test").unwrap();
 /// ```
-pub fn validate_project<'a>(project_path: &'a str, env: &'a str) {
+pub fn validate_project<'a>(project_path: &'a str, env: &'a str) -> Result<()> {
     //find deploy application.project,application-${env}.properties
     println!("validate project...");
 
@@ -34,25 +42,218 @@ pub fn validate_project<'a>(project_path: &'a str, env: &'a str) {
     }
     project_name.reverse();
     let project_name: String = project_name.into_iter().collect();
-    let propertiesFile = File::open(format!(
+    File::open(format!(
         "{}/src/main/resources/application-{}.properties",
         project_path, env
     ))
-    .unwrap_or_else(|_| {
+    .or_else(|_| {
         File::open(format!(
             "{}/src/main/resources/application-{}.yml",
             project_path, env
         ))
-        .expect("the properties file: application.properties or application.yml is not exists")
-    });
-    fix_package_name(&format!("{}/pom.xml", project_path), &project_name);
+    })
+    .map_err(|_| {
+        Error::Validation(format!(
+            "the properties file: application-{}.properties or application-{}.yml is not exists in {}",
+            env, env, project_path
+        ))
+    })?;
+    fix_profiles_active(&format!("{}/src/main/resources", project_path), env)?;
+    fix_package_name(&format!("{}/pom.xml", project_path), &project_name)
 }
 
-fn fix_package_name<'a>(pom_file: &'a str, package_name: &'a str) {
-    let content = fix_package_name_from_str(&fs::read_to_string(pom_file).unwrap(), package_name);
-    fs::write(pom_file, content).unwrap();
+/// set `spring.profiles.active` to `env` in application.properties or application.yml,
+/// application.properties is created when both of them are not exists.
+fn fix_profiles_active<'a>(resources_path: &'a str, env: &'a str) -> Result<()> {
+    let resources_path = Path::new(resources_path);
+    for name in &[
+        "application.properties",
+        "application.yml",
+        "application.yaml",
+    ] {
+        let file = resources_path.join(name);
+        if let Ok(content) = fs::read_to_string(&file) {
+            println!("set {} to {} in {}", PROFILES_ACTIVE, env, name);
+            let content = if name.ends_with(".properties") {
+                fix_profiles_active_properties_from_str(&content, env)
+            } else {
+                fix_profiles_active_yml_from_str(&content, env)?
+            };
+            fs::write(&file, content)?;
+            return Ok(());
+        }
+    }
+    println!(
+        "create application.properties with {}={}",
+        PROFILES_ACTIVE, env
+    );
+    fs::write(
+        resources_path.join("application.properties"),
+        format!("{}={}\n", PROFILES_ACTIVE, env),
+    )?;
+    Ok(())
 }
-fn fix_package_name_from_str<'a>(content: &'a str, package_name: &'a str) -> String {
+
+// replace every `spring.profiles.active` line, the other lines are unchanged
+fn fix_profiles_active_properties_from_str<'a>(content: &'a str, env: &'a str) -> String {
+    let mut result = String::with_capacity(content.len());
+    let mut found = false;
+    // a logical line may be continued by a trailing backslash
+    let mut logical = String::new();
+    for line in content.split_inclusive('\n') {
+        logical.push_str(line);
+        let trimmed = line.trim_end_matches(LINE_END_VEC);
+        let is_comment = logical.trim_start().starts_with(&['#', '!'][..]);
+        let backslashes = trimmed.len() - trimmed.trim_end_matches('\\').len();
+        if !is_comment && backslashes % 2 == 1 {
+            continue;
+        }
+        let key = PropertiesIter::new(logical.as_bytes())
+            .next()
+            .and_then(|line| line.ok())
+            .and_then(|line| match line.consume_content() {
+                LineContent::KVPair(key, _) => Some(key),
+                LineContent::Comment(_) => None,
+            });
+        if key.as_deref() == Some(PROFILES_ACTIVE) {
+            found = true;
+            result.push_str(&format!("{}={}", PROFILES_ACTIVE, env));
+            result.push_str(&line[trimmed.len()..]);
+        } else {
+            result.push_str(&logical);
+        }
+        logical.clear();
+    }
+    result.push_str(&logical);
+    if !found {
+        if !result.is_empty() && !result.ends_with('\n') {
+            result.push('\n');
+        }
+        result.push_str(&format!("{}={}\n", PROFILES_ACTIVE, env));
+    }
+    result
+}
+
+// split a yaml line into key and value, eg. `active: dev # comment` -> (active, dev)
+fn split_yml_key(line: &str) -> Option<(&str, &str)> {
+    if line.starts_with(&['-', '#', '[', '{', '?'][..]) {
+        return None;
+    }
+    let index = line.find(": ").or_else(|| {
+        if line.ends_with(':') {
+            Some(line.len() - 1)
+        } else {
+            None
+        }
+    })?;
+    let key = line[..index].trim().trim_matches(&['"', '\''][..]);
+    let value = line[index + 1..].trim();
+    let value = if value.starts_with('#') { "" } else { value };
+    Some((key, value))
+}
+
+// the trailing comment of a yaml line with the spaces before it, eg. `active: dev # comment` -> ` # comment`
+fn yml_comment(line: &str) -> Option<&str> {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => {
+                return Some(&line[line[..i].trim_end().len()..]);
+            }
+            None => {}
+        }
+        previous = c;
+    }
+    None
+}
+
+// replace `spring.profiles.active` of the first document,
+// in nested (spring: profiles: active:) or dotted (spring.profiles.active:) form.
+// the key is appended to the first document when missing.
+fn fix_profiles_active_yml_from_str<'a>(content: &'a str, env: &'a str) -> Result<String> {
+    let mut result = String::with_capacity(content.len());
+    // keys of the mappings containing the current line, with their indent
+    let mut parents: Vec<(usize, String)> = Vec::new();
+    let mut found = false;
+    let mut in_first_document = true;
+    let mut has_content = false;
+    let mut first_document_end = None;
+    // skip the old value of `active:` in block form, eg. a list of profiles
+    let mut skip_indent: Option<usize> = None;
+    for line in content.split_inclusive('\n') {
+        let trimmed_end = line.trim_end_matches(LINE_END_VEC);
+        let trimmed = trimmed_end.trim_start();
+        let indent = trimmed_end.len() - trimmed.len();
+        if trimmed_end.starts_with("---") || trimmed_end.starts_with("...") {
+            if has_content && in_first_document {
+                in_first_document = false;
+                first_document_end = Some(result.len());
+            }
+            skip_indent = None;
+            result.push_str(line);
+            continue;
+        }
+        if !in_first_document || trimmed.is_empty() || trimmed.starts_with('#') {
+            result.push_str(line);
+            continue;
+        }
+        has_content = true;
+        if let Some(skip) = skip_indent {
+            if indent > skip || (indent == skip && trimmed.starts_with('-')) {
+                continue;
+            }
+            skip_indent = None;
+        }
+        if let Some((key, value)) = split_yml_key(trimmed) {
+            while parents.last().is_some_and(|(i, _)| *i >= indent) {
+                parents.pop();
+            }
+            let mut path: Vec<&str> = parents.iter().map(|(_, key)| key.as_str()).collect();
+            path.push(key);
+            if path.join(".") == PROFILES_ACTIVE {
+                found = true;
+                result.push_str(&format!("{}{}: {}", &trimmed_end[..indent], key, env));
+                result.push_str(yml_comment(trimmed).unwrap_or_default());
+                result.push_str(&line[trimmed_end.len()..]);
+                if value.is_empty() {
+                    skip_indent = Some(indent);
+                }
+                continue;
+            }
+            if value.is_empty() {
+                parents.push((indent, String::from(key)));
+            }
+        }
+        result.push_str(line);
+    }
+    if !found {
+        let active = format!("{}: {}\n", PROFILES_ACTIVE, env);
+        match first_document_end {
+            Some(end) => result.insert_str(end, &active),
+            None => {
+                if !result.is_empty() && !result.ends_with('\n') {
+                    result.push('\n');
+                }
+                result.push_str(&active);
+            }
+        }
+    }
+    YamlLoader::load_from_str(&result)
+        .map_err(|e| Error::Validation(format!("invalid application.yml: {}", e)))?;
+    Ok(result)
+}
+
+/// set `<finalName>` in pom.xml to `package_name`
+pub fn fix_package_name<'a>(pom_file: &'a str, package_name: &'a str) -> Result<()> {
+    let content = fix_package_name_from_str(&fs::read_to_string(pom_file)?, package_name)?;
+    fs::write(pom_file, content)?;
+    Ok(())
+}
+pub fn fix_package_name_from_str<'a>(content: &'a str, package_name: &'a str) -> Result<String> {
     let mut reader = Reader::from_str(content);
     reader.trim_text(true);
     let mut writer = Writer::new(Cursor::new(Vec::new()));
@@ -66,53 +267,195 @@ fn fix_package_name_from_str<'a>(content: &'a str, package_name: &'a str) -> Str
                 if let Event::Start(ref x) = e {
                     if x.name() == b"finalName" {
                         finded_final_name = true;
-                        let final_name = &reader.read_text(x.name(), &mut Vec::new()).expect(
-                            "cannot decode project final name in tag: <finalName>xxx</finalName>",
-                        );
+                        let final_name = &reader.read_text(x.name(), &mut Vec::new())?;
                         if final_name != package_name {
                             println!(
                                 "this current finalName is {},and fix to {}",
                                 final_name, package_name
                             );
                             need_write = true;
-                            writer.write_event(e).unwrap();
-                            writer
-                                .write_event(Event::Text(BytesText::from_plain_str(package_name)))
-                                .unwrap();
-                            writer
-                                .write_event(Event::End(BytesEnd::owned(x.name().to_vec())))
-                                .unwrap();
+                            writer.write_event(e)?;
+                            writer.write_event(Event::Text(BytesText::from_plain_str(
+                                package_name,
+                            )))?;
+                            writer.write_event(Event::End(BytesEnd::owned(x.name().to_vec())))?;
                             continue;
                         } else {
                             println!("finalName is correct and does not need to be fixed");
                         }
                     }
                 } else if let Event::End(ref x) = e {
-                    if x.name() == b"build" && finded_final_name == false {
-                        writer
-                            .write_event(Event::Start(BytesStart::owned(
-                                b"finalName".to_vec(),
-                                "finalName".len(),
-                            )))
-                            .unwrap();
-                        writer
-                            .write_event(Event::Text(BytesText::from_plain_str(package_name)))
-                            .unwrap();
-                        writer
-                            .write_event(Event::End(BytesEnd::owned(b"finalName".to_vec())))
-                            .unwrap();
+                    if x.name() == b"build" && !finded_final_name {
+                        writer.write_event(Event::Start(BytesStart::owned(
+                            b"finalName".to_vec(),
+                            "finalName".len(),
+                        )))?;
+                        writer.write_event(Event::Text(BytesText::from_plain_str(package_name)))?;
+                        writer.write_event(Event::End(BytesEnd::owned(b"finalName".to_vec())))?;
                     }
                 }
-                writer.write_event(e).unwrap();
+                writer.write_event(e)?;
             }
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
+            Err(e) => return Err(Error::Xml(e)),
         }
         buf.clear();
     }
     if need_write {
-        String::from_utf8(writer.into_inner().into_inner()).unwrap()
+        String::from_utf8(writer.into_inner().into_inner())
+            .map_err(|e| Error::Xml(quick_xml::Error::Utf8(e.utf8_error())))
     } else {
-        content.to_owned()
+        Ok(content.to_owned())
+    }
+}
+
+/// update the `<version>` of matching dependencies in pom.xml,
+/// including those in `<dependencyManagement>`.
+///
+/// `updates` are gradle-style dependencies: `<groupId>:<artifactId>:<version>`,
+/// dependencies without `<version>` and unmatched dependencies are unchanged.
+pub fn fix_dependencies<'a>(pom_file: &'a str, updates: &'a [String]) -> Result<()> {
+    let content = fix_dependencies_from_str(&fs::read_to_string(pom_file)?, updates)?;
+    fs::write(pom_file, content)?;
+    Ok(())
+}
+
+// parse gradle-style dependency: <groupId>:<artifactId>:<version>
+pub(crate) fn parse_dependency(dependency: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = dependency.trim().split(':');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(group_id), Some(artifact_id), Some(version), None)
+            if !group_id.is_empty() && !artifact_id.is_empty() && !version.is_empty() =>
+        {
+            Some((group_id, artifact_id, version))
+        }
+        _ => None,
+    }
+}
+
+pub fn fix_dependencies_from_str<'a>(content: &'a str, updates: &'a [String]) -> Result<String> {
+    let updates: Vec<(&str, &str, &str)> = updates
+        .iter()
+        .filter_map(|dependency| {
+            let parsed = parse_dependency(dependency);
+            if parsed.is_none() {
+                println!(
+                    "ignore dependency {},the format should be <groupId>:<artifactId>:<version>",
+                    dependency
+                );
+            }
+            parsed
+        })
+        .collect();
+    if updates.is_empty() {
+        return Ok(content.to_owned());
+    }
+    let mut reader = Reader::from_str(content);
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let mut need_write = false;
+    // events of the current <dependency>, written after its groupId and artifactId are known
+    let mut dependency: Vec<Event<'static>> = Vec::new();
+    let mut in_dependency = false;
+    // depth inside <dependency>, the direct children are at depth 1
+    let mut depth = 0;
+    let mut tag: Vec<u8> = Vec::new();
+    let mut group_id = String::new();
+    let mut artifact_id = String::new();
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        let e = match reader.read_event(&mut buf) {
+            Ok(Event::Eof) => break,
+            Ok(e) => e,
+            Err(e) => return Err(Error::Xml(e)),
+        };
+        if !in_dependency {
+            if let Event::Start(ref x) = e {
+                if x.name() == b"dependency" {
+                    in_dependency = true;
+                    depth = 0;
+                    group_id.clear();
+                    artifact_id.clear();
+                    dependency.push(e.into_owned());
+                    continue;
+                }
+            }
+            writer.write_event(e)?;
+            continue;
+        }
+        match e {
+            Event::Start(ref x) => {
+                depth += 1;
+                if depth == 1 {
+                    tag = x.name().to_vec();
+                }
+            }
+            Event::End(ref x) if depth == 0 && x.name() == b"dependency" => {
+                dependency.push(e.into_owned());
+                in_dependency = false;
+                let version = updates
+                    .iter()
+                    .find(|(g, a, _)| *g == group_id && *a == artifact_id)
+                    .map(|(_, _, version)| *version);
+                let last = dependency.len() - 1;
+                for (i, e) in dependency.drain(..).enumerate() {
+                    // <dependency> and </dependency>
+                    if i == 0 || i == last {
+                        writer.write_event(e)?;
+                        continue;
+                    }
+                    let e = match (version, e) {
+                        (Some(version), Event::Text(ref x))
+                            if depth == 1 && tag.as_slice() == b"version" =>
+                        {
+                            let current = x.unescape_and_decode(&reader)?;
+                            if current.trim() == version {
+                                Event::Text(x.clone())
+                            } else {
+                                println!(
+                                    "update dependency {}:{} version {} to {}",
+                                    group_id,
+                                    artifact_id,
+                                    current.trim(),
+                                    version
+                                );
+                                need_write = true;
+                                Event::Text(BytesText::from_plain_str(version).into_owned())
+                            }
+                        }
+                        (_, e) => e,
+                    };
+                    match e {
+                        Event::Start(ref x) => {
+                            depth += 1;
+                            if depth == 1 {
+                                tag = x.name().to_vec();
+                            }
+                        }
+                        Event::End(_) if depth > 0 => depth -= 1,
+                        _ => {}
+                    }
+                    writer.write_event(e)?;
+                }
+                continue;
+            }
+            Event::End(_) => depth -= 1,
+            Event::Text(ref x) if depth == 1 => {
+                let text = x.unescape_and_decode(&reader)?;
+                if tag.as_slice() == b"groupId" {
+                    group_id = text.trim().to_owned();
+                } else if tag.as_slice() == b"artifactId" {
+                    artifact_id = text.trim().to_owned();
+                }
+            }
+            _ => {}
+        }
+        dependency.push(e.into_owned());
+    }
+    if need_write {
+        String::from_utf8(writer.into_inner().into_inner())
+            .map_err(|e| Error::Xml(quick_xml::Error::Utf8(e.utf8_error())))
+    } else {
+        Ok(content.to_owned())
     }
 }
 
@@ -120,13 +463,13 @@ fn fix_package_name_from_str<'a>(content: &'a str, package_name: &'a str) -> Str
 fn when_final_name_not_match_then_fix_it() {
     let content = fs::read_to_string("./tests/pom.xml").unwrap();
     assert_eq!(557, content.find("demo-test").unwrap());
-    let content = fix_package_name_from_str(&content, "test_1");
+    let content = fix_package_name_from_str(&content, "test_1").unwrap();
     assert_eq!(493, content.find("test_1").unwrap());
 }
 #[test]
 fn when_final_name_not_exists_then_fix_it() {
     let content = fs::read_to_string("./tests/pom-with-no-final-name.xml").unwrap();
-    let content = fix_package_name_from_str(&content, "test_1");
+    let content = fix_package_name_from_str(&content, "test_1").unwrap();
     assert_eq!(493, content.find("test_1").unwrap());
 }
 
@@ -134,6 +477,99 @@ fn when_final_name_not_exists_then_fix_it() {
 fn when_final_name_matches_then_dont_fix_it() {
     let content = fs::read_to_string("./tests/pom.xml").unwrap();
     assert_eq!(557, content.find("demo-test").unwrap());
-    let content = fix_package_name_from_str(&content, "demo-test");
+    let content = fix_package_name_from_str(&content, "demo-test").unwrap();
     assert_eq!(557, content.find("demo-test").unwrap());
 }
+
+#[test]
+fn when_dependencies_match_then_update_version() {
+    let content = fs::read_to_string("./tests/pom-with-dependencies.xml").unwrap();
+    let updates = vec![
+        String::from("site.zido:demo-common:0.0.2-test"),
+        String::from("site.zido:demo-search:0.0.3-test"),
+    ];
+    let fixed = fix_dependencies_from_str(&content, &updates).unwrap();
+    assert_eq!(
+        content
+            .replacen("0.0.1", "0.0.2-test", 1)
+            .replacen("0.0.1", "0.0.3-test", 1),
+        fixed
+    );
+}
+
+#[test]
+fn when_dependencies_not_match_then_dont_fix_it() {
+    let content = fs::read_to_string("./tests/pom-with-dependencies.xml").unwrap();
+    let updates = vec![
+        String::from("site.zido:demo-other:0.0.2"),
+        String::from("junit:junit:4.12"),
+        String::from("site.zido:demo-common"),
+    ];
+    assert_eq!(
+        content,
+        fix_dependencies_from_str(&content, &updates).unwrap()
+    );
+}
+
+#[test]
+fn when_properties_has_profiles_active_then_replace_it() {
+    let content = fs::read_to_string("./tests/application.properties").unwrap();
+    let content = fix_profiles_active_properties_from_str(&content, "test");
+    assert_eq!(
+        "# 总控配置文件\nserver.port=8080\nspring.profiles.active=test\n\
+         ! 其他配置\nspring.application.name=demo\n",
+        content
+    );
+}
+
+#[test]
+fn when_properties_has_no_profiles_active_then_append_it() {
+    let content = fix_profiles_active_properties_from_str("# comment\nserver.port=8080", "test");
+    assert_eq!(
+        "# comment\nserver.port=8080\nspring.profiles.active=test\n",
+        content
+    );
+}
+
+#[test]
+fn when_yml_has_profiles_active_then_replace_it() {
+    let content = fs::read_to_string("./tests/application.yml").unwrap();
+    let fixed = fix_profiles_active_yml_from_str(&content, "test").unwrap();
+    assert_eq!(
+        content.replace("active:\n      - dev\n      - local\n", "active: test\n"),
+        fixed
+    );
+    let docs = YamlLoader::load_from_str(&fixed).unwrap();
+    assert_eq!(
+        Some("test"),
+        docs[0]["spring"]["profiles"]["active"].as_str()
+    );
+    assert_eq!(Some("test"), docs[1]["spring"]["profiles"].as_str());
+}
+
+#[test]
+fn when_yml_has_no_profiles_active_then_append_to_first_document() {
+    let content = "spring:\n  application:\n    name: demo\n---\nserver:\n  port: 8081\n";
+    let fixed = fix_profiles_active_yml_from_str(content, "test").unwrap();
+    assert_eq!(
+        "spring:\n  application:\n    name: demo\nspring.profiles.active: test\n\
+         ---\nserver:\n  port: 8081\n",
+        fixed
+    );
+}
+
+#[test]
+fn when_yml_has_dotted_profiles_active_then_replace_it() {
+    let content = "spring.profiles.active: dev # 当前环境\nserver.port: 8080\n";
+    let fixed = fix_profiles_active_yml_from_str(content, "test").unwrap();
+    assert_eq!(
+        "spring.profiles.active: test # 当前环境\nserver.port: 8080\n",
+        fixed
+    );
+    let content = "spring:\n  profiles:\n    active: \"dev#1\"  # 'quoted'\n";
+    let fixed = fix_profiles_active_yml_from_str(content, "test").unwrap();
+    assert_eq!(
+        "spring:\n  profiles:\n    active: test  # 'quoted'\n",
+        fixed
+    );
+}
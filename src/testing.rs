@@ -0,0 +1,24 @@
+//! helpers shared by the unit tests
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// a new empty directory for a test, unique among the tests of concurrent test runs
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+        "auto-deploy-{}-{}-{}",
+        name,
+        process::id(),
+        NEXT_DIR.fetch_add(1, Ordering::SeqCst)
+    ));
+    // left by an earlier run with the same process id
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}